@@ -0,0 +1,95 @@
+use std::time::Duration;
+
+// the decision on a client request that is received by the server in charge of ordering requests,
+// i.e. the pbft primary or the unreplicated server
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Admit {
+    Accept,
+    // reply `Busy` to the client, and forget about the request. the client backs off its resending
+    Reject,
+    // silently drop the request. client will resend it later, and hopefully the overload has gone
+    // by then
+    Defer,
+}
+
+pub trait Admission {
+    // `num_queued` is the number of requests that have been accepted, but not yet been working on
+    // e.g. for pbft, not yet proposed because there are already `num_concurrent` consensus
+    // instances in progress. unreplicated server executes requests right away, so it's always 0
+    // there, and only client-based policies make sense
+    fn admit(&mut self, client_id: u32, num_queued: usize) -> Admit;
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct AdmitAll;
+
+impl Admission for AdmitAll {
+    fn admit(&mut self, _: u32, _: usize) -> Admit {
+        Admit::Accept
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct QueueLimit(pub usize);
+
+impl Admission for QueueLimit {
+    fn admit(&mut self, _: u32, num_queued: usize) -> Admit {
+        if num_queued < self.0 {
+            Admit::Accept
+        } else {
+            Admit::Reject
+        }
+    }
+}
+
+// the client side of `Admit::Reject`: on `Busy` the resending slows down exponentially (up to a
+// cap), so the rejected clients stop hammering the overloaded server. `Admit::Defer` gets no such
+// treatment, since the client cannot tell a deferred request from a lost one
+// meant to be created per invocation, i.e. a new invocation resends at the normal pace again
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct Backoff {
+    num_backoff: u32,
+    // back off at most once between two resendings. a single sending may get rejected multiple
+    // times, e.g. pbft client broadcasts and the backups relay the request to the primary
+    backed_off: bool,
+}
+
+const MAX_NUM_BACKOFF: u32 = 5;
+
+impl Backoff {
+    // on receiving a `Busy` of the outstanding request. return the new resend interval if the
+    // resend timer should be reset with it
+    pub fn busy(&mut self, resend_interval: Duration) -> Option<Duration> {
+        if self.backed_off {
+            return None;
+        }
+        self.backed_off = true;
+        self.num_backoff = (self.num_backoff + 1).min(MAX_NUM_BACKOFF);
+        Some(resend_interval * 2u32.pow(self.num_backoff))
+    }
+
+    pub fn resend(&mut self) {
+        self.backed_off = false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff() {
+        let interval = Duration::from_millis(100);
+        let mut backoff = Backoff::default();
+        assert_eq!(backoff.busy(interval), Some(interval * 2));
+        assert_eq!(backoff.busy(interval), None);
+        backoff.resend();
+        assert_eq!(backoff.busy(interval), Some(interval * 4));
+        for _ in 0..10 {
+            backoff.resend();
+            backoff.busy(interval);
+        }
+        backoff.resend();
+        assert_eq!(backoff.busy(interval), Some(interval * 32))
+    }
+}
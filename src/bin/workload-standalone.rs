@@ -3,15 +3,11 @@ use std::{
     time::{Duration, Instant},
 };
 
-use neatworks::{pbft::PublicParameters, workload::events::Invoke};
-use tokio::{select, time::sleep};
-use workload::util::run_until;
-
-pub mod workload {
-    pub mod clients;
-    pub mod servers;
-    pub mod util;
-}
+use neatworks::{
+    pbft::PublicParameters,
+    workload::{self, cluster::Cluster, events::Invoke},
+};
+use tokio::{sync::mpsc::unbounded_channel, time::sleep};
 
 struct InvokeTask;
 
@@ -35,14 +31,31 @@ impl workload::clients::InvokeTask for InvokeTask {
     }
 }
 
+// default max number of received messages that are queued up for the (lagging) server state
+// machine (including the ones pending in the crypto worker), override with the second argument
+const RECV_BOUND: usize = 1 << 10;
+
 #[tokio::main(flavor = "current_thread")]
 async fn main() -> anyhow::Result<()> {
+    // prints the pbft milestones (target `neatworks::pbft::milestone`) along with timestamps
+    #[cfg(feature = "tracing-subscriber")]
+    tracing_subscriber::fmt::init();
     let mode = args().nth(1);
+    let recv_bound = args()
+        .nth(2)
+        .map(|arg| arg.parse())
+        .transpose()?
+        .unwrap_or(RECV_BOUND);
+    anyhow::ensure!(recv_bound > 0, "receive bound must be positive");
     match mode.as_deref().unwrap_or("unreplicated") {
         "unreplicated" => {
-            let server_task = workload::servers::unreplicated();
-            let client_task = workload::clients::unreplicated(InvokeTask);
-            run_until(client_task, server_task).await
+            let mut cluster = Cluster::unreplicated(recv_bound).await?;
+            let client_task = workload::clients::unreplicated(
+                InvokeTask,
+                cluster.addrs[0],
+                unbounded_channel().1,
+            );
+            cluster.run(client_task).await
         }
         "pbft" => {
             let config = PublicParameters {
@@ -56,23 +69,14 @@ async fn main() -> anyhow::Result<()> {
                     Duration::from_millis(100)
                 })
             };
-            let addrs = (0..4)
-                .map(|index| ([127, 0, 0, 1 + index], 3000).into())
-                .collect::<Vec<_>>();
-            let server_task0 = workload::servers::pbft(config.clone(), 0, addrs.clone());
-            let server_task1 = workload::servers::pbft(config.clone(), 1, addrs.clone());
-            let server_task2 = workload::servers::pbft(config.clone(), 2, addrs.clone());
-            let server_task3 = workload::servers::pbft(config.clone(), 3, addrs.clone());
-            let client_task = workload::clients::pbft(InvokeTask, config, addrs);
-            run_until(client_task, async {
-                select! {
-                    result = server_task0 => result,
-                    result = server_task1 => result,
-                    result = server_task2 => result,
-                    result = server_task3 => result,
-                }
-            })
-            .await
+            let mut cluster = Cluster::pbft(&config, recv_bound).await?;
+            let client_task = workload::clients::pbft(
+                InvokeTask,
+                config,
+                cluster.addrs.clone(),
+                unbounded_channel().1,
+            );
+            cluster.run(client_task).await
         }
         _ => anyhow::bail!("unimplemented"),
     }
@@ -5,7 +5,7 @@ use serde::{de::DeserializeOwned, Deserialize, Serialize};
 
 use crate::{
     event::SendEvent,
-    net::events::Cast,
+    net::{combinators::Reconfigure, events::Cast},
     workload::{
         events::{Invoke, InvokeOk},
         App, Workload,
@@ -23,6 +23,13 @@ impl<M: Into<L>, L, N: SendEvent<Cast<A, Bytes>>, A> SendEvent<Cast<A, M>> for E
     }
 }
 
+// control events pass through untouched, to the underlying net that actually knows the addresses
+impl<M, N: SendEvent<Reconfigure<I, A>>, I, A> SendEvent<Reconfigure<I, A>> for Encode<M, N> {
+    fn send(&mut self, event: Reconfigure<I, A>) -> anyhow::Result<()> {
+        self.1.send(event)
+    }
+}
+
 impl<M, E: SendEvent<InvokeOk<Bytes>>> SendEvent<InvokeOk<M>> for Encode<M, E> {
     fn send(&mut self, InvokeOk(result): InvokeOk<M>) -> anyhow::Result<()> {
         let encoded = (self.0)(&result)?;
@@ -115,6 +115,10 @@ impl<C, S> Untyped<C, S> {
     pub fn new(state: S) -> Self {
         Self(state, Default::default())
     }
+
+    pub fn into_inner(self) -> S {
+        self.0
+    }
 }
 
 #[allow(clippy::type_complexity)]
@@ -1,20 +1,30 @@
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    future::{pending, Future},
+    sync::{Arc, Mutex, MutexGuard},
+};
 
 use derive_where::derive_where;
 use tokio::{
     select, spawn,
-    sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
+    sync::{
+        mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
+        OwnedSemaphorePermit, Semaphore,
+    },
     task::{AbortHandle, JoinSet},
     time::interval,
 };
 
-use super::{ActiveTimer, OnEvent, ScheduleEvent, SendEvent, UntypedEvent};
+use super::{ActiveTimer, OnEvent, ScheduleEvent, SendEvent, Submit, UntypedEvent, Work};
 
 pub mod erase {
     use crate::event::{Erase, UntypedEvent};
 
     pub type Sender<S, C> = Erase<S, C, super::UnboundedSender<UntypedEvent<S, C>>>;
 
+    pub type BoundedSender<S, C> =
+        Erase<S, C, super::Bounded<super::UnboundedSender<UntypedEvent<S, C>>>>;
+
     pub type ScheduleState<S, C> = Erase<S, C, super::ScheduleState<UntypedEvent<S, C>>>;
 }
 
@@ -25,6 +35,87 @@ impl<M: Into<N>, N> SendEvent<M> for UnboundedSender<N> {
     }
 }
 
+// credit based flow control between a producer task (e.g. the receiving loop of a socket) and the
+// state machine that consumes the produced events
+// the producer waits for a credit before producing each event, and the credit is given back after
+// the state machine finishes processing that event, so at most `bound` events from the producer
+// can be queued in the (still unbounded) channel. a lagging state machine then stalls the producer
+// instead of growing the channel; for a socket the excess turns into OS buffer overflow and packet
+// loss, which the protocols are prepared for anyway
+// processing an event may only hand the heavy lifting over to a worker, e.g. pbft replica submits
+// the received message for verification. for the backlog to not just move into the worker's queue,
+// a work submitted through `Bounded` during processing takes over the event's credit, which is then
+// given back after the work is done. the worker is bounded as a result, while the follow up events
+// it sends back are not accounted. they are bounded by the worker anyway
+// only events and works that go through `Bounded` are accounted, so the other senders of the same
+// channel (e.g. the timers) are never blocked, and the state machine is not deadlocked on its own
+// follow up events
+#[derive(Debug, Clone)]
+pub struct Credit {
+    semaphore: Arc<Semaphore>,
+    // the credit of the `Bounded` event that is being processed
+    processing: Arc<Mutex<Option<OwnedSemaphorePermit>>>,
+}
+
+impl Credit {
+    // `bound` should be positive. a zero bound never lets anything through, i.e. `ready` blocks
+    // forever
+    pub fn new(bound: usize) -> Self {
+        Self {
+            semaphore: Arc::new(Semaphore::new(bound)),
+            processing: Default::default(),
+        }
+    }
+
+    pub async fn ready(&self) -> anyhow::Result<()> {
+        // the permit is given back immediately, and the actual one is taken by `Bounded` when
+        // sending. there is supposed to be a single producer, so nobody can take the credit away
+        // in between
+        let _ = self.semaphore.acquire().await?;
+        Ok(())
+    }
+
+    fn processing(&self) -> MutexGuard<'_, Option<OwnedSemaphorePermit>> {
+        // nothing panics while holding the lock
+        self.processing.lock().unwrap()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Bounded<E>(pub E, pub Credit);
+
+impl<E: SendEvent<UntypedEvent<S, C>>, S: 'static, C: 'static> SendEvent<UntypedEvent<S, C>>
+    for Bounded<E>
+{
+    fn send(&mut self, UntypedEvent(event): UntypedEvent<S, C>) -> anyhow::Result<()> {
+        let permit = self
+            .1
+            .semaphore
+            .clone()
+            .try_acquire_owned()
+            .map_err(|_| anyhow::format_err!("sending without credit"))?;
+        let credit = self.1.clone();
+        self.0.send(UntypedEvent(Box::new(move |state, context| {
+            credit.processing().replace(permit);
+            let result = event(state, context);
+            // given back here, unless some work has taken it over
+            credit.processing().take();
+            result
+        })))
+    }
+}
+
+impl<E: Submit<S, C>, S: 'static, C: 'static> Submit<S, C> for Bounded<E> {
+    fn submit(&mut self, work: Work<S, C>) -> anyhow::Result<()> {
+        let permit = self.1.processing().take();
+        self.0.submit(Box::new(move |state, context| {
+            let result = work(state, context);
+            drop(permit);
+            result
+        }))
+    }
+}
+
 pub mod work {
     use crate::event::{SendEvent, Submit, UntypedEvent, Work};
 
@@ -142,6 +233,29 @@ pub async fn run<M, C>(
     .await
 }
 
+pub async fn run_until(
+    task: impl Future<Output = anyhow::Result<()>>,
+    background_task: impl Future<Output = anyhow::Result<()>>,
+) -> anyhow::Result<()> {
+    select! {
+        result = background_task => result?,
+        result = task => return result,
+    }
+    anyhow::bail!("unexpected termination of forever task")
+}
+
+// forward the events from a side channel, e.g. the control events from an operator, into an event
+// loop. the side channel getting closed only means there will be no more such events
+pub async fn forward<M>(
+    receiver: &mut UnboundedReceiver<M>,
+    mut sender: impl SendEvent<M>,
+) -> anyhow::Result<()> {
+    while let Some(event) = receiver.recv().await {
+        sender.send(event)?
+    }
+    pending().await
+}
+
 pub async fn run_worker<S: Clone + Send + 'static, C: Clone + Send + 'static>(
     state: S,
     context: C,
@@ -166,3 +280,39 @@ pub async fn run_worker<S: Clone + Send + 'static, C: Clone + Send + 'static>(
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type Worker = Bounded<work::Sender<(), ()>>;
+
+    #[test]
+    fn credit_taken_over_by_work() -> anyhow::Result<()> {
+        let credit = Credit::new(1);
+        let (sender, mut receiver) = unbounded_channel::<UntypedEvent<(), Worker>>();
+        let (work_sender, mut work_receiver) = unbounded_channel();
+        let mut worker = Bounded(work_sender, credit.clone());
+
+        let mut bounded = Bounded(sender, credit.clone());
+        bounded.send(UntypedEvent(Box::new(|(), _| Ok(()))))?;
+        anyhow::ensure!(bounded
+            .send(UntypedEvent(Box::new(|(), _| Ok(()))))
+            .is_err());
+        let UntypedEvent(event) = receiver.try_recv()?;
+        event(&mut (), &mut worker)?;
+        anyhow::ensure!(credit.semaphore.available_permits() == 1);
+
+        bounded.send(UntypedEvent(Box::new(|(), worker: &mut Worker| {
+            worker.submit(Box::new(|(), ()| Ok(())))
+        })))?;
+        let UntypedEvent(event) = receiver.try_recv()?;
+        event(&mut (), &mut worker)?;
+        // the submitted work is still pending, and holding the credit
+        anyhow::ensure!(credit.semaphore.available_permits() == 0);
+        let UntypedEvent(work) = work_receiver.try_recv()?;
+        work(&mut (), &mut ())?;
+        anyhow::ensure!(credit.semaphore.available_permits() == 1);
+        Ok(())
+    }
+}
@@ -1,9 +1,11 @@
+pub mod admission;
 pub mod codec;
 pub mod crypto;
 pub mod event;
 pub mod model;
 pub mod net;
 pub mod pbft;
+pub mod quorum;
 pub mod timer;
 pub mod unreplicated;
 pub mod workload; // better name that clearly shows unrelated to `worker`?
@@ -13,6 +13,21 @@ impl<A: Addr, N: SendEvent<Cast<A, M>>, M> SendEvent<Cast<(), M>> for Forward<A,
     }
 }
 
+// control event that swaps the address behind a destination, e.g. point to a replacement that is
+// brought up on another host after the original one crashed
+// the identity of the destination (the index, and the key that bound to it) is unchanged, so this
+// is safe to be performed locally on the network layer without any protocol coordination. changing
+// the membership itself is a different story, see the notes in `pbft`
+#[derive(Debug, Clone)]
+pub struct Reconfigure<I, A>(pub I, pub A);
+
+impl<A: Addr, N> SendEvent<Reconfigure<(), A>> for Forward<A, N> {
+    fn send(&mut self, Reconfigure((), addr): Reconfigure<(), A>) -> anyhow::Result<()> {
+        self.0 = addr;
+        Ok(())
+    }
+}
+
 #[derive(Debug)]
 pub struct All;
 
@@ -46,6 +61,17 @@ impl<A: Addr, N: SendEvent<Cast<A, M>>, M, I: Into<usize>> SendEvent<Cast<I, M>>
     }
 }
 
+impl<A: Addr, N, I: Into<usize>> SendEvent<Reconfigure<I, A>> for IndexNet<A, N> {
+    fn send(&mut self, Reconfigure(index, addr): Reconfigure<I, A>) -> anyhow::Result<()> {
+        let index = index.into();
+        let Some(slot) = self.addrs.get_mut(index) else {
+            anyhow::bail!("missing address of index {index}")
+        };
+        *slot = addr;
+        Ok(())
+    }
+}
+
 impl<A: Addr, N: SendEvent<Cast<A, Bytes>>> SendEvent<Cast<All, Bytes>> for IndexNet<A, N> {
     fn send(&mut self, Cast(All, message): Cast<All, Bytes>) -> anyhow::Result<()> {
         for (index, addr) in self.addrs.iter().enumerate() {
@@ -57,3 +83,46 @@ impl<A: Addr, N: SendEvent<Cast<A, Bytes>>> SendEvent<Cast<All, Bytes>> for Inde
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::event::combinators::Transient;
+
+    use super::*;
+
+    #[test]
+    fn swap_addr() -> anyhow::Result<()> {
+        let mut net = IndexNet::new(
+            vec![10u8, 11, 12],
+            0usize,
+            Transient::<Cast<u8, Bytes>>::new(),
+        );
+        SendEvent::send(&mut net, Cast(1usize, Bytes::from("foo")))?;
+        SendEvent::send(&mut net, Reconfigure(1usize, 21u8))?;
+        SendEvent::send(&mut net, Cast(1usize, Bytes::from("bar")))?;
+        SendEvent::send(&mut net, Cast(All, Bytes::from("baz")))?;
+        let sent = net
+            .inner
+            .drain(..)
+            .map(|Cast(addr, message)| (addr, message))
+            .collect::<Vec<_>>();
+        anyhow::ensure!(
+            sent == [
+                (11, Bytes::from("foo")),
+                (21, Bytes::from("bar")),
+                (21, Bytes::from("baz")),
+                (12, Bytes::from("baz")),
+            ],
+            "{sent:?}"
+        );
+        anyhow::ensure!(SendEvent::send(&mut net, Reconfigure(3usize, 13u8)).is_err());
+
+        let mut forward = Forward(10u8, Transient::<Cast<u8, Bytes>>::new());
+        SendEvent::send(&mut forward, Reconfigure((), 20u8))?;
+        SendEvent::send(&mut forward, Cast((), Bytes::from("foo")))?;
+        let Some(Cast(20, _)) = forward.1.pop() else {
+            anyhow::bail!("message not forwarded to swapped address")
+        };
+        Ok(())
+    }
+}
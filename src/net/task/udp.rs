@@ -3,7 +3,10 @@ use std::{net::SocketAddr, sync::Arc};
 use bytes::Bytes;
 use tokio::{net::UdpSocket, spawn};
 
-use crate::{event::SendEvent, net::events::Cast};
+use crate::{
+    event::{task::Credit, SendEvent},
+    net::events::Cast,
+};
 
 impl SendEvent<Cast<SocketAddr, Bytes>> for Arc<UdpSocket> {
     fn send(&mut self, Cast(remote, message): Cast<SocketAddr, Bytes>) -> anyhow::Result<()> {
@@ -27,3 +30,78 @@ pub async fn run(
         on_buf(&buf[..len])?
     }
 }
+
+// the `on_buf` is expected to send (at most) one event through a `Bounded` that shares `credit`
+pub async fn run_with_credit(
+    socket: &UdpSocket,
+    credit: &Credit,
+    mut on_buf: impl FnMut(&[u8]) -> anyhow::Result<()>,
+) -> anyhow::Result<()> {
+    let mut buf = vec![0; 64 << 10];
+    loop {
+        credit.ready().await?;
+        let (len, _) = socket.recv_from(&mut buf).await?;
+        on_buf(&buf[..len])?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::{sync::mpsc::unbounded_channel, time::timeout};
+
+    use crate::event::{task::Bounded, UntypedEvent};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn stall_without_credit() -> anyhow::Result<()> {
+        let socket = UdpSocket::bind(SocketAddr::from(([127, 0, 0, 1], 0))).await?;
+        let addr = socket.local_addr()?;
+        let (sender, mut receiver) = unbounded_channel::<UntypedEvent<Vec<u8>, ()>>();
+        let credit = Credit::new(2);
+        let mut bounded = Bounded(sender, credit.clone());
+        let recv_task = spawn(async move {
+            run_with_credit(&socket, &credit, |buf| {
+                let buf = buf.to_vec();
+                bounded.send(UntypedEvent(Box::new(move |state, ()| {
+                    state.extend(buf);
+                    Ok(())
+                })))
+            })
+            .await
+        });
+
+        let client = UdpSocket::bind(SocketAddr::from(([127, 0, 0, 1], 0))).await?;
+        for i in 0..4 {
+            client.send_to(&[i], addr).await?;
+        }
+        let mut events = Vec::new();
+        for _ in 0..2 {
+            events.push(
+                timeout(Duration::from_secs(1), receiver.recv())
+                    .await?
+                    .unwrap(),
+            )
+        }
+        // the rest are left in the socket, since the received ones are not processed yet
+        anyhow::ensure!(timeout(Duration::from_millis(100), receiver.recv())
+            .await
+            .is_err());
+
+        let mut state = Vec::new();
+        for UntypedEvent(event) in events {
+            event(&mut state, &mut ())?
+        }
+        for _ in 0..2 {
+            let UntypedEvent(event) = timeout(Duration::from_secs(1), receiver.recv())
+                .await?
+                .unwrap();
+            event(&mut state, &mut ())?
+        }
+        anyhow::ensure!(state == [0, 1, 2, 3], "{state:?}");
+        recv_task.abort();
+        Ok(())
+    }
+}
@@ -3,14 +3,20 @@ use std::collections::BTreeMap;
 use bytes::Bytes;
 
 use crate::{
+    admission::Backoff,
     codec::Payload,
     event::{ActiveTimer, OnErasedEvent, ScheduleEvent, SendEvent},
-    net::{combinators::All, events::Recv, Addr, SendMessage},
+    net::{
+        combinators::{All, Reconfigure},
+        events::Recv,
+        Addr, SendMessage,
+    },
+    unreplicated,
     workload::events::{Invoke, InvokeOk},
 };
 
 use super::{
-    messages::{Reply, Request},
+    messages::{Busy, Reply, Request},
     PublicParameters,
 };
 
@@ -19,6 +25,8 @@ pub struct State<A> {
     id: u32,
     addr: A,
     config: PublicParameters,
+    num_reply: usize,
+    token: Option<u64>,
 
     seq: u32,
     outstanding: Option<Outstanding>,
@@ -30,6 +38,7 @@ struct Outstanding {
     op: Payload,
     replies: BTreeMap<u8, Reply>,
     timer: ActiveTimer,
+    backoff: Backoff,
 }
 
 impl<A> State<A> {
@@ -37,13 +46,52 @@ impl<A> State<A> {
         Self {
             id,
             addr,
+            num_reply: config.num_faulty + 1,
             config,
+            token: None,
 
             seq: 0,
             outstanding: Default::default(),
             view_num: 0,
         }
     }
+
+    // `num_reply` is the number of matching replies to be collected before delivering the result
+    // f + 1 (the default) is the minimum that guarantees at least one of the replies comes from a
+    // correct replica. collecting more costs latency, but e.g. confirms that a quorum of replicas
+    // has executed the request. it must not be more than n - f, otherwise faulty replicas can
+    // block the client forever by not replying
+    pub fn with_num_reply(self, num_reply: usize) -> anyhow::Result<Self> {
+        anyhow::ensure!(
+            num_reply > self.config.num_faulty
+                && num_reply <= self.config.num_replica - self.config.num_faulty,
+            "invalid number of replies {num_reply}"
+        );
+        Ok(Self { num_reply, ..self })
+    }
+
+    // see `replica::State::from_unreplicated`. the client carries on with its identity and the
+    // numbering, so its requests that have been executed by the unreplicated server are not
+    // executed again by the replicas. there must be no outstanding invocation
+    pub fn from_unreplicated(
+        client: unreplicated::ClientState<A>,
+        config: PublicParameters,
+    ) -> anyhow::Result<Self> {
+        let (id, addr, token, seq) = client.into_parts()?;
+        Ok(Self {
+            token,
+            seq,
+            ..Self::new(id, addr, config)
+        })
+    }
+
+    // see `messages::Request` for the token
+    pub fn with_token(self, token: u64) -> Self {
+        Self {
+            token: Some(token),
+            ..self
+        }
+    }
 }
 
 pub mod events {
@@ -69,6 +117,7 @@ impl<A: Addr, C: Context<A>> OnErasedEvent<Invoke<Bytes>, C> for State<A> {
                 .schedule()
                 .set(self.config.client_resend_interval, events::Resend)?,
             replies: Default::default(),
+            backoff: Default::default(),
         });
         anyhow::ensure!(replaced.is_none());
         self.send_request(
@@ -78,9 +127,13 @@ impl<A: Addr, C: Context<A>> OnErasedEvent<Invoke<Bytes>, C> for State<A> {
     }
 }
 
+// the timer is armed on sending the request and keeps ticking until enough replies are collected
+// on every tick the request is broadcast to all replicas, so even if the primary ignores (or not
+// receives) the request, the backups will relay it to the primary and start the view change timer
 impl<A: Addr, C: Context<A>> OnErasedEvent<events::Resend, C> for State<A> {
     fn on_event(&mut self, events::Resend: events::Resend, context: &mut C) -> anyhow::Result<()> {
         // warn!("Resend timeout on seq {}", self.seq);
+        self.outstanding.as_mut().unwrap().backoff.resend();
         self.send_request(All, context)
     }
 }
@@ -95,12 +148,14 @@ impl<A, C: Context<A>> OnErasedEvent<Recv<Reply>, C> for State<A> {
         };
         invoke.replies.insert(reply.replica_id, reply.clone());
         // println!("{:?}", invoke.replies);
+        // the replies beyond `num_reply` (including duplicated ones) are harmless: the outstanding
+        // invocation is gone after the first time this passes
         if invoke
             .replies
             .values()
             .filter(|inserted_reply| inserted_reply.result == reply.result)
             .count()
-            != self.config.num_faulty + 1
+            < self.num_reply
         {
             return Ok(());
         }
@@ -115,6 +170,42 @@ impl<A, C: Context<A>> OnErasedEvent<Recv<Reply>, C> for State<A> {
     }
 }
 
+// the primary rejected the request. keep the request outstanding, but resend it less frequently
+// since it is likely to be rejected again in the near future
+// the backing off is also delaying the view change that is triggered by resending, which is fine:
+// a primary that is replying `Busy` is alive anyway
+// only the primary (of the current view or a later one) may reply `Busy`. otherwise a faulty backup
+// could keep the client backing off, and delay the view change to replace a faulty primary
+impl<A, C: Context<A>> OnErasedEvent<Recv<Busy>, C> for State<A> {
+    fn on_event(&mut self, Recv(busy): Recv<Busy>, context: &mut C) -> anyhow::Result<()> {
+        if busy.seq != self.seq
+            || busy.view_num < self.view_num
+            || busy.replica_id != (busy.view_num as usize % self.config.num_replica) as u8
+        {
+            return Ok(());
+        }
+        let Some(invoke) = self.outstanding.as_mut() else {
+            return Ok(());
+        };
+        let Some(interval) = invoke.backoff.busy(self.config.client_resend_interval) else {
+            return Ok(());
+        };
+        context.schedule().unset(invoke.timer.clone())?;
+        invoke.timer = context.schedule().set(interval, events::Resend)?;
+        Ok(())
+    }
+}
+
+impl<A, C: Context<A>> OnErasedEvent<Reconfigure<u8, A>, C> for State<A>
+where
+    C::Net: SendEvent<Reconfigure<u8, A>>,
+{
+    fn on_event(&mut self, event: Reconfigure<u8, A>, context: &mut C) -> anyhow::Result<()> {
+        // the outstanding request (if any) reaches the swapped address on the next resending
+        SendEvent::send(context.net(), event)
+    }
+}
+
 impl<A: Addr> State<A> {
     fn send_request<B, C: Context<A>>(&mut self, dest: B, context: &mut C) -> anyhow::Result<()>
     where
@@ -123,6 +214,7 @@ impl<A: Addr> State<A> {
         let request = Request {
             client_id: self.id,
             client_addr: self.addr.clone(),
+            token: self.token,
             seq: self.seq,
             op: self.outstanding.as_ref().unwrap().op.clone(),
         };
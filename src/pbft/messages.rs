@@ -1,4 +1,4 @@
-use std::collections::BTreeMap;
+use std::hash::{Hash, Hasher};
 
 use serde::{Deserialize, Serialize};
 
@@ -7,12 +7,32 @@ use crate::{
     crypto::{Verifiable, H256},
 };
 
-#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
 pub struct Request<A> {
     pub seq: u32,
     pub op: Payload,
     pub client_id: u32,
     pub client_addr: A,
+    // optional identity of the client's incarnation. a client that may restart (and start over its
+    // `seq` from 1) while keeping its `client_id` picks a fresh token on every start, so the
+    // replicas keep executing its requests instead of taking them as stale retransmissions
+    // the requests without token keep the wire format from before the field was added, see
+    // `codec::ToReplica`
+    pub token: Option<u64>,
+}
+
+// the token is left out of the hash when absent, so the digest of a batch of token-less requests
+// (which `PrePrepare` signs) stays the same as the ones computed before the field was added
+impl<A: Hash> Hash for Request<A> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.seq.hash(state);
+        self.op.hash(state);
+        self.client_id.hash(state);
+        self.client_addr.hash(state);
+        if let Some(token) = self.token {
+            token.hash(state)
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
@@ -46,6 +66,14 @@ pub struct Reply {
     pub replica_id: u8,
 }
 
+// primary is not admitting the request for now, see `crate::admission::Admission`
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct Busy {
+    pub seq: u32,
+    pub view_num: u32,
+    pub replica_id: u8,
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
 pub struct ViewChange {
     pub view_num: u32,
@@ -73,7 +101,7 @@ pub struct QueryNewView {
     pub replica_id: u8,
 }
 
-pub type Quorum<M> = BTreeMap<u8, Verifiable<M>>;
+pub use crate::quorum::Quorum;
 
 pub mod codec {
     use derive_more::From;
@@ -87,27 +115,102 @@ pub mod codec {
 
     use super::*;
 
-    pub type ToClient = Reply;
+    // the replies were sent bare before `Busy` was added, and bincode cannot tell a bare `Reply`
+    // from this enum. this is a breaking change of the client-bound wire format: the clients built
+    // before cannot decode the replies from the replicas after, so upgrade the clients along with
+    // the replicas
+    #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize, From)]
+    pub enum ToClient {
+        Reply(Reply),
+        Busy(Busy),
+    }
 
     pub fn to_client_encode<N>(net: N) -> Encode<ToClient, N> {
         Encode::bincode(net)
     }
 
     pub fn to_client_decode<'a>(
-        mut sender: impl SendEvent<Recv<Reply>> + 'a,
+        mut sender: impl SendEvent<Recv<Reply>> + SendEvent<Recv<Busy>> + 'a,
     ) -> impl FnMut(&[u8]) -> anyhow::Result<()> + 'a {
-        move |buf| sender.send(Recv(bincode::decode(buf)?))
+        use ToClient::*;
+        move |buf| match bincode::decode(buf)? {
+            Reply(message) => sender.send(Recv(message)),
+            Busy(message) => sender.send(Recv(message)),
+        }
+    }
+
+    // the `Request` layout before `token` was added
+    #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+    pub struct LegacyRequest<A> {
+        pub seq: u32,
+        pub op: Payload,
+        pub client_id: u32,
+        pub client_addr: A,
+    }
+
+    impl<A> From<LegacyRequest<A>> for Request<A> {
+        fn from(request: LegacyRequest<A>) -> Self {
+            Self {
+                seq: request.seq,
+                op: request.op,
+                client_id: request.client_id,
+                client_addr: request.client_addr,
+                token: None,
+            }
+        }
+    }
+
+    // only for the requests without token
+    fn legacy<A>(request: Request<A>) -> LegacyRequest<A> {
+        assert!(request.token.is_none());
+        LegacyRequest {
+            seq: request.seq,
+            op: request.op,
+            client_id: request.client_id,
+            client_addr: request.client_addr,
+        }
     }
 
+    // the variants carrying requests with token are appended to the end, so the indices and
+    // layouts of the existing variants stay the same, and the encodings from the nodes built before
+    // `Request::token` was added are still decoded (as requests without token). in the other
+    // direction, the requests without token are encoded into the legacy variants, so the older
+    // nodes keep working until tokens are used
     #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize, From)]
     pub enum ToReplica<A> {
-        Request(Request<A>),
-        PrePrepare(Verifiable<PrePrepare>, Vec<Request<A>>),
+        #[from(ignore)]
+        Request(LegacyRequest<A>),
+        #[from(ignore)]
+        PrePrepare(Verifiable<PrePrepare>, Vec<LegacyRequest<A>>),
         Prepare(Verifiable<Prepare>),
         Commit(Verifiable<Commit>),
         ViewChange(Verifiable<ViewChange>),
         NewView(Verifiable<NewView>),
         QueryNewView(QueryNewView),
+        #[from(ignore)]
+        RequestWithToken(Request<A>),
+        #[from(ignore)]
+        PrePrepareWithToken(Verifiable<PrePrepare>, Vec<Request<A>>),
+    }
+
+    impl<A> From<Request<A>> for ToReplica<A> {
+        fn from(request: Request<A>) -> Self {
+            if request.token.is_none() {
+                Self::Request(legacy(request))
+            } else {
+                Self::RequestWithToken(request)
+            }
+        }
+    }
+
+    impl<A> From<(Verifiable<PrePrepare>, Vec<Request<A>>)> for ToReplica<A> {
+        fn from((pre_prepare, requests): (Verifiable<PrePrepare>, Vec<Request<A>>)) -> Self {
+            if requests.iter().all(|request| request.token.is_none()) {
+                Self::PrePrepare(pre_prepare, requests.into_iter().map(legacy).collect())
+            } else {
+                Self::PrePrepareWithToken(pre_prepare, requests)
+            }
+        }
     }
 
     pub fn to_replica_encode<A: Addr, N>(net: N) -> Encode<ToReplica<A>, N> {
@@ -126,13 +229,99 @@ pub mod codec {
     ) -> impl FnMut(&[u8]) -> anyhow::Result<()> + 'a {
         use ToReplica::*;
         move |buf| match bincode::decode(buf)? {
-            Request(message) => sender.send(Recv(message)),
-            PrePrepare(message, requests) => sender.send(Recv((message, requests))),
+            Request(message) => sender.send(Recv(super::Request::from(message))),
+            PrePrepare(message, requests) => {
+                let requests = requests.into_iter().map(Into::into).collect();
+                sender.send(Recv((message, requests)))
+            }
             Prepare(message) => sender.send(Recv(message)),
             Commit(message) => sender.send(Recv(message)),
             ViewChange(message) => sender.send(Recv(message)),
             NewView(message) => sender.send(Recv(message)),
             QueryNewView(message) => sender.send(Recv(message)),
+            RequestWithToken(message) => sender.send(Recv(message)),
+            PrePrepareWithToken(message, requests) => sender.send(Recv((message, requests))),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use bytes::Bytes;
+
+    use crate::{
+        codec::bincode,
+        crypto::{Crypto, CryptoFlavor, DigestHash as _},
+    };
+
+    use super::{codec::ToReplica, *};
+
+    // the `Request` and the leading variants of `ToReplica`, as of before adding `Request::token`
+    #[derive(Hash, Serialize)]
+    struct LegacyRequest {
+        seq: u32,
+        op: Payload,
+        client_id: u32,
+        client_addr: SocketAddr,
+    }
+
+    #[derive(Serialize)]
+    enum LegacyToReplica {
+        Request(LegacyRequest),
+        PrePrepare(Verifiable<PrePrepare>, Vec<LegacyRequest>),
+    }
+
+    #[test]
+    fn legacy_request() -> anyhow::Result<()> {
+        let client_addr = SocketAddr::from(([127, 0, 0, 1], 10000));
+        let legacy_request = || LegacyRequest {
+            seq: 1,
+            op: Payload(Bytes::from("foo")),
+            client_id: 42,
+            client_addr,
+        };
+        let request = Request {
+            seq: 1,
+            op: Payload(Bytes::from("foo")),
+            client_id: 42,
+            client_addr,
+            token: None,
+        };
+
+        let legacy = bincode::encode(&LegacyToReplica::Request(legacy_request()))?;
+        let ToReplica::Request(decoded) = bincode::decode::<ToReplica<SocketAddr>>(&legacy)? else {
+            anyhow::bail!("unexpected variant")
+        };
+        anyhow::ensure!(Request::from(decoded) == request);
+        // and the older replicas can decode the requests without token from now on
+        anyhow::ensure!(bincode::encode(&ToReplica::from(request.clone()))? == legacy);
+
+        anyhow::ensure!(vec![request.clone()].sha256() == vec![legacy_request()].sha256());
+        let pre_prepare = Crypto::new_hardcoded(4, 0u8, CryptoFlavor::Plain)?.sign(PrePrepare {
+            view_num: 0,
+            op_num: 1,
+            digest: vec![legacy_request()].sha256(),
+        });
+        let legacy = bincode::encode(&LegacyToReplica::PrePrepare(
+            pre_prepare.clone(),
+            vec![legacy_request()],
+        ))?;
+        anyhow::ensure!(
+            bincode::encode(&ToReplica::from((pre_prepare, vec![request.clone()])))? == legacy
+        );
+
+        let request = Request {
+            token: Some(1),
+            ..request
+        };
+        let ToReplica::RequestWithToken(decoded) =
+            bincode::decode(&bincode::encode(&ToReplica::from(request.clone()))?)?
+        else {
+            anyhow::bail!("unexpected variant")
+        };
+        anyhow::ensure!(decoded == request);
+        Ok(())
+    }
+}
@@ -6,6 +6,22 @@ pub mod replica;
 #[cfg(test)]
 pub mod tests;
 
+// reconfiguration
+// replacing the address of a failed replica (while keeping its index and key) needs nothing from
+// the protocol: send `net::combinators::Reconfigure` to the other replicas and the clients (both
+// forward it to their `IndexNet`s, see e.g. `workload::cluster::Control`), and the replacement
+// catches up through the regular retransmission (state transfer once that lands)
+// changing `num_replica`/`num_faulty` or the keys on the other hand must go through the protocol
+// to stay safe: the reconfiguration is proposed as a special operation, ordered and committed like
+// any other one, and takes effect (i.e. the quorum sizes and the primary rotation switch to the new
+// configuration) starting from the next view after it is executed, so that no quorum mixes
+// replicas from two configurations. not implemented yet
+// switching a deployment from `unreplicated` to pbft (the unreplicated-to-replicated transition)
+// happens outside of both protocols, since they share no message format: the server stops and hands
+// over its state, the replicas start on it (`replica::State::from_unreplicated`), and the clients
+// move on to the replicas, either restarted or carrying on with their identities
+// (`client::State::from_unreplicated`). see `workload::cluster::Cluster::upgrade` for the runtime
+// side, which is driven by the `workload::servers::Handover` control event
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct PublicParameters {
     pub num_replica: usize,
@@ -1,30 +1,52 @@
 use std::collections::BTreeMap;
 
 use crate::{
+    admission::{Admission, Admit, AdmitAll},
     codec::Payload,
     crypto::{
         events::{Signed, Verified},
         Crypto, DigestHash, Verifiable, H256,
     },
-    event::{OnErasedEvent, ScheduleEvent, SendEventFor, Submit},
-    net::{combinators::All, events::Recv, Addr, SendMessage},
+    event::{OnErasedEvent, ScheduleEvent, SendEvent, SendEventFor, Submit},
+    net::{
+        combinators::{All, Reconfigure},
+        events::Recv,
+        Addr, SendMessage,
+    },
+    quorum::QuorumCert,
     timer::Timer,
+    unreplicated,
     workload::App,
 };
 
 use super::{
     messages::{
-        Commit, NewView, PrePrepare, Prepare, QueryNewView, Quorum, Reply, Request, ViewChange,
+        Busy, Commit, NewView, PrePrepare, Prepare, QueryNewView, Quorum, Reply, Request,
+        ViewChange,
     },
     PublicParameters,
 };
 
+// protocol milestones as structured events for offline analysis, e.g. per-phase latencies by
+// joining the events of the same `op_num` across replicas. the timestamps are up to the subscriber
+// compiled out without the `tracing` feature
+macro_rules! milestone {
+    ($($arg:tt)*) => {
+        #[cfg(feature = "tracing")]
+        tracing::info!(target: "neatworks::pbft::milestone", $($arg)*)
+    };
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
-pub struct State<S, A> {
+pub struct State<S, A, P = AdmitAll> {
     id: u8,
     config: PublicParameters,
+    admission: P,
 
-    replies: BTreeMap<u32, (u32, Option<Reply>)>, // client id -> (seq, result)
+    // (client id, token) -> (seq, result)
+    // the entries of a client's previous incarnations are never looked up again, but are kept
+    // around until there's a way to tell they are obsolete
+    replies: BTreeMap<(u32, Option<u64>), (u32, Option<Reply>)>,
     requests: Vec<Request<A>>,
     view_num: u32,
     new_views: BTreeMap<u32, Verifiable<NewView>>,
@@ -36,14 +58,14 @@ pub struct State<S, A> {
     // a more consistent design may be log[0] also has some `pre_prepare` and becomes a regular
     // no-op slot, but i don't bother
     log: Vec<LogEntry<A>>,
-    prepare_quorums: Quorums<u32, Prepare>, // u32 = op number
-    commit_quorums: Quorums<u32, Commit>,
+    prepare_quorums: QuorumCert<u32, Prepare>, // u32 = op number
+    commit_quorums: QuorumCert<u32, Commit>,
     commit_num: u32,
     app: S,
 
     do_view_change_timer: Timer<events::DoViewChange>,
     progress_view_change_timer: Timer<events::ProgressViewChange>,
-    view_changes: Quorums<u32, ViewChange>, // u32 = view number
+    view_changes: QuorumCert<u32, ViewChange>, // u32 = view number
 
     // any op num presents in this maps -> there's ongoing verification submitted
     // entry presents but empty list -> no pending but one is verifying
@@ -53,8 +75,6 @@ pub struct State<S, A> {
     pending_commits: BTreeMap<u32, Vec<Verifiable<Commit>>>,
 }
 
-type Quorums<K, M> = BTreeMap<K, Quorum<M>>;
-
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 struct LogEntry<A> {
     pre_prepare: Option<Verifiable<PrePrepare>>,
@@ -70,25 +90,61 @@ const NO_OP_DIGEST: H256 = H256::zero();
 
 impl<S, A> State<S, A> {
     pub fn new(id: u8, app: S, config: PublicParameters) -> Self {
+        Self::with_admission(id, app, config, AdmitAll)
+    }
+
+    // the unreplicated-to-replicated transition: start on the final state of an unreplicated
+    // server, which should have stopped serving by then. every replica starts with a copy of the
+    // server's app, as if all of them have executed the same requests before the first view, and
+    // with the server's client table, so the executed requests are still deduplicated (and answered
+    // with the cached results) when they are retransmitted to the replicas
+    pub fn from_unreplicated<P>(
+        id: u8,
+        server: unreplicated::ServerState<S, P>,
+        config: PublicParameters,
+    ) -> Self {
+        let (app, replies) = server.into_parts();
+        let replies = replies
+            .map(|(key, seq, result)| {
+                let reply = Reply {
+                    seq,
+                    result,
+                    view_num: 0,
+                    replica_id: id,
+                };
+                (key, (seq, Some(reply)))
+            })
+            .collect();
+        Self {
+            replies,
+            ..Self::new(id, app, config)
+        }
+    }
+}
+
+impl<S, A, P> State<S, A, P> {
+    pub fn with_admission(id: u8, app: S, config: PublicParameters, admission: P) -> Self {
         let (
             replies,
             requests,
             view_num,
             new_views,
             log,
-            prepare_quorums,
-            commit_quorums,
             commit_num,
-            view_changes,
             pending_prepares,
             pending_commits,
         ) = Default::default();
         Self {
             id,
             app,
+            admission,
 
             do_view_change_timer: Timer::new(config.view_change_delay),
             progress_view_change_timer: Timer::new(config.progress_view_change_interval),
+            // the primary's PrePrepare counts as its Prepare
+            prepare_quorums: QuorumCert::new(config.num_replica - config.num_faulty - 1),
+            commit_quorums: QuorumCert::new(config.num_replica - config.num_faulty),
+            view_changes: QuorumCert::new(config.num_replica - config.num_faulty),
             config,
 
             replies,
@@ -96,10 +152,7 @@ impl<S, A> State<S, A> {
             view_num,
             new_views,
             log,
-            prepare_quorums,
-            commit_quorums,
             commit_num,
-            view_changes,
             pending_prepares,
             pending_commits,
         }
@@ -122,7 +175,7 @@ pub mod events {
 
 pub trait Context<S, A> {
     type PeerNet: PeerNet<A>;
-    type DownlinkNet: SendMessage<A, Reply>;
+    type DownlinkNet: SendMessage<A, Reply> + SendMessage<A, Busy>;
     type CryptoWorker: Submit<Crypto, Self::CryptoContext>;
     type CryptoContext: SendEventFor<S, Self>;
     type Schedule: Schedule;
@@ -183,7 +236,7 @@ trait ContextExt<S, A>: Context<S, A> {
 }
 impl<C: Context<S, A>, S, A> ContextExt<S, A> for C {}
 
-impl<S, A> State<S, A> {
+impl<S, A, P> State<S, A, P> {
     fn is_primary(&self) -> bool {
         (self.view_num as usize % self.config.num_replica) == self.id as usize
     }
@@ -208,12 +261,14 @@ impl<S, A> State<S, A> {
     }
 }
 
-impl<S: App, A: Addr, C: Context<Self, A>> OnErasedEvent<Recv<Request<A>>, C> for State<S, A> {
+impl<S: App, A: Addr, P: Admission, C: Context<Self, A>> OnErasedEvent<Recv<Request<A>>, C>
+    for State<S, A, P>
+{
     fn on_event(&mut self, Recv(request): Recv<Request<A>>, context: &mut C) -> anyhow::Result<()> {
         if self.view_change() {
             return Ok(());
         }
-        match self.replies.get(&request.client_id) {
+        match self.replies.get(&(request.client_id, request.token)) {
             Some((seq, _)) if *seq > request.seq => return Ok(()),
             Some((seq, reply)) if *seq == request.seq => {
                 if let Some(reply) = reply {
@@ -234,7 +289,28 @@ impl<S: App, A: Addr, C: Context<Self, A>> OnErasedEvent<Recv<Request<A>>, C> fo
                 .ensure_set(events::DoViewChange(self.view_num + 1), context.schedule())?;
             return Ok(());
         }
-        self.replies.insert(request.client_id, (request.seq, None));
+        // the admission only happens on primary, since it is the only one that queues up requests.
+        // backups relay the requests to the primary regardless
+        match self.admission.admit(request.client_id, self.requests.len()) {
+            Admit::Accept => {}
+            Admit::Reject => {
+                let busy = Busy {
+                    seq: request.seq,
+                    view_num: self.view_num,
+                    replica_id: self.id,
+                };
+                return context.downlink_net().send(request.client_addr, busy);
+            }
+            Admit::Defer => return Ok(()),
+        }
+        milestone!(
+            replica = self.id,
+            client_id = request.client_id,
+            seq = request.seq,
+            "request"
+        );
+        self.replies
+            .insert((request.client_id, request.token), (request.seq, None));
         self.requests.push(request);
         if self.op_num() <= self.commit_num + self.config.num_concurrent as u32 {
             self.close_batch(context)
@@ -244,7 +320,7 @@ impl<S: App, A: Addr, C: Context<Self, A>> OnErasedEvent<Recv<Request<A>>, C> fo
     }
 }
 
-impl<S: App, A: Addr> State<S, A> {
+impl<S: App, A: Addr, P> State<S, A, P> {
     fn close_batch(&mut self, context: &mut impl Context<Self, A>) -> anyhow::Result<()> {
         assert!(self.is_primary());
         assert!(!self.view_change());
@@ -272,8 +348,8 @@ impl<S: App, A: Addr> State<S, A> {
     }
 }
 
-impl<S: App, A: Addr, C: Context<Self, A>> OnErasedEvent<(Signed<PrePrepare>, Vec<Request<A>>), C>
-    for State<S, A>
+impl<S: App, A: Addr, P, C: Context<Self, A>>
+    OnErasedEvent<(Signed<PrePrepare>, Vec<Request<A>>), C> for State<S, A, P>
 {
     fn on_event(
         &mut self,
@@ -290,6 +366,13 @@ impl<S: App, A: Addr, C: Context<Self, A>> OnErasedEvent<(Signed<PrePrepare>, Ve
             .pre_prepare
             .replace(pre_prepare.clone());
         assert!(replaced.is_none());
+        milestone!(
+            replica = self.id,
+            view_num = self.view_num,
+            op_num,
+            num_request = requests.len(),
+            "pre-prepared"
+        );
 
         self.log[op_num as usize].requests.clone_from(&requests);
         self.log[op_num as usize]
@@ -322,7 +405,9 @@ impl<S: App, A: Addr, C: Context<Self, A>> OnErasedEvent<(Signed<PrePrepare>, Ve
 // should be safe since we are just resending old PrePrepare
 // (if the old PrePrepare is gone (probably because of a view change), the timer
 // should be gone along with it)
-impl<S, A: Addr, C: Context<Self, A>> OnErasedEvent<events::ProgressPrepare, C> for State<S, A> {
+impl<S, A: Addr, P, C: Context<Self, A>> OnErasedEvent<events::ProgressPrepare, C>
+    for State<S, A, P>
+{
     fn on_event(
         &mut self,
         events::ProgressPrepare(op_num): events::ProgressPrepare,
@@ -340,8 +425,8 @@ impl<S, A: Addr, C: Context<Self, A>> OnErasedEvent<events::ProgressPrepare, C>
     }
 }
 
-impl<S: App, A: Addr, C: Context<Self, A>>
-    OnErasedEvent<Recv<(Verifiable<PrePrepare>, Vec<Request<A>>)>, C> for State<S, A>
+impl<S: App, A: Addr, P, C: Context<Self, A>>
+    OnErasedEvent<Recv<(Verifiable<PrePrepare>, Vec<Request<A>>)>, C> for State<S, A, P>
 {
     fn on_event(
         &mut self,
@@ -392,8 +477,8 @@ impl<S: App, A: Addr, C: Context<Self, A>>
     }
 }
 
-impl<S: App, A: Addr, C: Context<Self, A>> OnErasedEvent<(Verified<PrePrepare>, Vec<Request<A>>), C>
-    for State<S, A>
+impl<S: App, A: Addr, P, C: Context<Self, A>>
+    OnErasedEvent<(Verified<PrePrepare>, Vec<Request<A>>), C> for State<S, A, P>
 {
     fn on_event(
         &mut self,
@@ -413,6 +498,13 @@ impl<S: App, A: Addr, C: Context<Self, A>> OnErasedEvent<(Verified<PrePrepare>,
                 return Ok(());
             }
         }
+        milestone!(
+            replica = self.id,
+            view_num = self.view_num,
+            op_num = pre_prepare.op_num,
+            num_request = requests.len(),
+            "pre-prepared"
+        );
         self.log[pre_prepare.op_num as usize].pre_prepare = Some(pre_prepare.clone());
         self.log[pre_prepare.op_num as usize].requests = requests;
 
@@ -424,21 +516,17 @@ impl<S: App, A: Addr, C: Context<Self, A>> OnErasedEvent<(Verified<PrePrepare>,
         };
         context.submit_sign(prepare)?;
 
-        if let Some(prepare_quorum) = self.prepare_quorums.get_mut(&pre_prepare.op_num) {
-            prepare_quorum.retain(|_, prepare| {
-                prepare.view_num == pre_prepare.view_num && prepare.digest == pre_prepare.digest
-            });
-        }
-        if let Some(commit_quorum) = self.commit_quorums.get_mut(&pre_prepare.op_num) {
-            commit_quorum.retain(|_, commit| {
-                commit.view_num == pre_prepare.view_num && commit.digest == pre_prepare.digest
-            })
-        }
+        self.prepare_quorums.retain(&pre_prepare.op_num, |prepare| {
+            prepare.view_num == pre_prepare.view_num && prepare.digest == pre_prepare.digest
+        });
+        self.commit_quorums.retain(&pre_prepare.op_num, |commit| {
+            commit.view_num == pre_prepare.view_num && commit.digest == pre_prepare.digest
+        });
         Ok(())
     }
 }
 
-impl<S: App, A: Addr, C: Context<Self, A>> OnErasedEvent<Signed<Prepare>, C> for State<S, A> {
+impl<S: App, A: Addr, P, C: Context<Self, A>> OnErasedEvent<Signed<Prepare>, C> for State<S, A, P> {
     fn on_event(
         &mut self,
         Signed(prepare): Signed<Prepare>,
@@ -455,8 +543,8 @@ impl<S: App, A: Addr, C: Context<Self, A>> OnErasedEvent<Signed<Prepare>, C> for
     }
 }
 
-impl<S: App, A: Addr, C: Context<Self, A>> OnErasedEvent<Recv<Verifiable<Prepare>>, C>
-    for State<S, A>
+impl<S: App, A: Addr, P, C: Context<Self, A>> OnErasedEvent<Recv<Verifiable<Prepare>>, C>
+    for State<S, A, P>
 {
     fn on_event(
         &mut self,
@@ -481,7 +569,7 @@ impl<S: App, A: Addr, C: Context<Self, A>> OnErasedEvent<Recv<Verifiable<Prepare
     }
 }
 
-impl<S: App, A: Addr> State<S, A> {
+impl<S: App, A: Addr, P> State<S, A, P> {
     fn submit_prepare(
         &mut self,
         prepare: Verifiable<Prepare>,
@@ -530,7 +618,9 @@ impl<S: App, A: Addr> State<S, A> {
     }
 }
 
-impl<S: App, A: Addr, C: Context<Self, A>> OnErasedEvent<Verified<Prepare>, C> for State<S, A> {
+impl<S: App, A: Addr, P, C: Context<Self, A>> OnErasedEvent<Verified<Prepare>, C>
+    for State<S, A, P>
+{
     fn on_event(
         &mut self,
         Verified(prepare): Verified<Prepare>,
@@ -558,21 +648,17 @@ impl<S: App, A: Addr, C: Context<Self, A>> OnErasedEvent<Verified<Prepare>, C> f
     }
 }
 
-impl<S: App, A: Addr> State<S, A> {
+impl<S: App, A: Addr, P> State<S, A, P> {
     fn insert_prepare(
         &mut self,
         prepare: Verifiable<Prepare>,
         context: &mut impl Context<Self, A>,
     ) -> anyhow::Result<()> {
-        let prepare_quorum = self.prepare_quorums.entry(prepare.op_num).or_default();
-        prepare_quorum.insert(prepare.replica_id, prepare.clone());
-        // println!(
-        //     "{} PrePrepare {} Prepare {}",
-        //     prepare.op_num,
-        //     self.log.get(prepare.op_num as usize).is_some(),
-        //     prepare_quorum.len()
-        // );
-        if prepare_quorum.len() + 1 < self.config.num_replica - self.config.num_faulty {
+        if self
+            .prepare_quorums
+            .insert(prepare.op_num, prepare.replica_id, prepare.clone())
+            .is_none()
+        {
             return Ok(());
         }
         let Some(entry) = self.log.get_mut(prepare.op_num as usize) else {
@@ -585,6 +671,12 @@ impl<S: App, A: Addr> State<S, A> {
         assert!(entry.prepares.is_empty());
         entry.prepares = self.prepare_quorums.remove(&prepare.op_num).unwrap();
         self.pending_prepares.remove(&prepare.op_num);
+        milestone!(
+            replica = self.id,
+            view_num = self.view_num,
+            op_num = prepare.op_num,
+            "prepared"
+        );
 
         let commit = Commit {
             view_num: self.view_num,
@@ -596,7 +688,7 @@ impl<S: App, A: Addr> State<S, A> {
     }
 }
 
-impl<S: App, A: Addr, C: Context<Self, A>> OnErasedEvent<Signed<Commit>, C> for State<S, A> {
+impl<S: App, A: Addr, P, C: Context<Self, A>> OnErasedEvent<Signed<Commit>, C> for State<S, A, P> {
     fn on_event(&mut self, Signed(commit): Signed<Commit>, context: &mut C) -> anyhow::Result<()> {
         if commit.view_num != self.view_num {
             return Ok(());
@@ -609,8 +701,8 @@ impl<S: App, A: Addr, C: Context<Self, A>> OnErasedEvent<Signed<Commit>, C> for
     }
 }
 
-impl<S: App, A: Addr, C: Context<Self, A>> OnErasedEvent<Recv<Verifiable<Commit>>, C>
-    for State<S, A>
+impl<S: App, A: Addr, P, C: Context<Self, A>> OnErasedEvent<Recv<Verifiable<Commit>>, C>
+    for State<S, A, P>
 {
     fn on_event(
         &mut self,
@@ -630,7 +722,7 @@ impl<S: App, A: Addr, C: Context<Self, A>> OnErasedEvent<Recv<Verifiable<Commit>
     }
 }
 
-impl<S: App, A: Addr> State<S, A> {
+impl<S: App, A: Addr, P> State<S, A, P> {
     fn submit_commit(
         &mut self,
         commit: Verifiable<Commit>,
@@ -669,7 +761,9 @@ impl<S: App, A: Addr> State<S, A> {
     }
 }
 
-impl<S: App, A: Addr, C: Context<Self, A>> OnErasedEvent<Verified<Commit>, C> for State<S, A> {
+impl<S: App, A: Addr, P, C: Context<Self, A>> OnErasedEvent<Verified<Commit>, C>
+    for State<S, A, P>
+{
     fn on_event(
         &mut self,
         Verified(commit): Verified<Commit>,
@@ -697,23 +791,17 @@ impl<S: App, A: Addr, C: Context<Self, A>> OnErasedEvent<Verified<Commit>, C> fo
     }
 }
 
-impl<S: App, A: Addr> State<S, A> {
+impl<S: App, A: Addr, P> State<S, A, P> {
     fn insert_commit(
         &mut self,
         commit: Verifiable<Commit>,
         context: &mut impl Context<Self, A>,
     ) -> anyhow::Result<()> {
-        let commit_quorum = self.commit_quorums.entry(commit.op_num).or_default();
-        commit_quorum.insert(commit.replica_id, commit.clone());
-        // println!(
-        //     "[{}] {} PrePrepare {} Commit {}",
-        //     self.id,
-        //     commit.op_num,
-        //     self.log.get(commit.op_num as usize).is_some(),
-        //     commit_quorum.len()
-        // );
-
-        if commit_quorum.len() < self.config.num_replica - self.config.num_faulty {
+        if self
+            .commit_quorums
+            .insert(commit.op_num, commit.replica_id, commit.clone())
+            .is_none()
+        {
             return Ok(());
         }
         let is_primary = self.is_primary();
@@ -728,6 +816,12 @@ impl<S: App, A: Addr> State<S, A> {
         log_entry.commits = self.commit_quorums.remove(&commit.op_num).unwrap();
         self.pending_commits.remove(&commit.op_num);
         // println!("[{}] Commit {}", self.id, commit.op_num);
+        milestone!(
+            replica = self.id,
+            view_num = self.view_num,
+            op_num = commit.op_num,
+            "committed"
+        );
         if is_primary {
             log_entry.progress_timer.unset(context.schedule())?;
         } else {
@@ -744,6 +838,13 @@ impl<S: App, A: Addr> State<S, A> {
             }
             self.commit_num += 1;
             // println!("[{}] Execute {}", self.id, self.commit_num);
+            milestone!(
+                replica = self.id,
+                view_num = pre_prepare.view_num,
+                op_num = self.commit_num,
+                num_request = log_entry.requests.len(),
+                "executed"
+            );
             log_entry
                 .state_transfer_timer
                 .ensure_unset(context.schedule())?;
@@ -761,12 +862,14 @@ impl<S: App, A: Addr> State<S, A> {
                 // the later request has been captured by `replies`, so not assert anything
                 if self
                     .replies
-                    .get(&request.client_id)
+                    .get(&(request.client_id, request.token))
                     .map(|(seq, _)| *seq <= request.seq)
                     .unwrap_or(true)
                 {
-                    self.replies
-                        .insert(request.client_id, (request.seq, Some(reply.clone())));
+                    self.replies.insert(
+                        (request.client_id, request.token),
+                        (request.seq, Some(reply.clone())),
+                    );
                 }
                 context
                     .downlink_net()
@@ -791,7 +894,7 @@ impl<S: App, A: Addr> State<S, A> {
     }
 }
 
-impl<S, A, C: Context<Self, A>> OnErasedEvent<events::StateTransfer, C> for State<S, A> {
+impl<S, A, P, C: Context<Self, A>> OnErasedEvent<events::StateTransfer, C> for State<S, A, P> {
     fn on_event(
         &mut self,
         events::StateTransfer(op_num): events::StateTransfer,
@@ -802,7 +905,19 @@ impl<S, A, C: Context<Self, A>> OnErasedEvent<events::StateTransfer, C> for Stat
     }
 }
 
-impl<S, A, C: Context<Self, A>> OnErasedEvent<Recv<QueryNewView>, C> for State<S, A> {
+// see `net::combinators::Reconfigure` and the notes in `pbft` for what this does (not) cover
+// the messages that were sent to the previous address and lost are recovered through the regular
+// resending of the protocol
+impl<S, A, P, C: Context<Self, A>> OnErasedEvent<Reconfigure<u8, A>, C> for State<S, A, P>
+where
+    C::PeerNet: SendEvent<Reconfigure<u8, A>>,
+{
+    fn on_event(&mut self, event: Reconfigure<u8, A>, context: &mut C) -> anyhow::Result<()> {
+        SendEvent::send(context.peer_net(), event)
+    }
+}
+
+impl<S, A, P, C: Context<Self, A>> OnErasedEvent<Recv<QueryNewView>, C> for State<S, A, P> {
     fn on_event(
         &mut self,
         Recv(query_new_view): Recv<QueryNewView>,
@@ -817,13 +932,16 @@ impl<S, A, C: Context<Self, A>> OnErasedEvent<Recv<QueryNewView>, C> for State<S
     }
 }
 
-impl<S: App, A: Addr, C: Context<Self, A>> OnErasedEvent<events::DoViewChange, C> for State<S, A> {
+impl<S: App, A: Addr, P, C: Context<Self, A>> OnErasedEvent<events::DoViewChange, C>
+    for State<S, A, P>
+{
     fn on_event(
         &mut self,
         events::DoViewChange(view_num): events::DoViewChange,
         context: &mut C,
     ) -> anyhow::Result<()> {
         // warn!("[{}] do view change for view {view_num}", self.id);
+        milestone!(replica = self.id, view_num, "view change");
         assert!(view_num >= self.view_num);
         self.view_num = view_num;
         // let DoViewChange(also_view_num) =
@@ -836,8 +954,8 @@ impl<S: App, A: Addr, C: Context<Self, A>> OnErasedEvent<events::DoViewChange, C
     }
 }
 
-impl<S: App, A: Addr, C: Context<Self, A>> OnErasedEvent<events::ProgressViewChange, C>
-    for State<S, A>
+impl<S: App, A: Addr, P, C: Context<Self, A>> OnErasedEvent<events::ProgressViewChange, C>
+    for State<S, A, P>
 {
     fn on_event(
         &mut self,
@@ -848,7 +966,7 @@ impl<S: App, A: Addr, C: Context<Self, A>> OnErasedEvent<events::ProgressViewCha
     }
 }
 
-impl<S: App, A: Addr> State<S, A> {
+impl<S: App, A: Addr, P> State<S, A, P> {
     fn do_view_change(&mut self, context: &mut impl Context<Self, A>) -> Result<(), anyhow::Error> {
         let log = self
             .log
@@ -870,7 +988,9 @@ impl<S: App, A: Addr> State<S, A> {
     }
 }
 
-impl<S: App, A: Addr, C: Context<Self, A>> OnErasedEvent<Signed<ViewChange>, C> for State<S, A> {
+impl<S: App, A: Addr, P, C: Context<Self, A>> OnErasedEvent<Signed<ViewChange>, C>
+    for State<S, A, P>
+{
     fn on_event(
         &mut self,
         Signed(view_change): Signed<ViewChange>,
@@ -902,8 +1022,8 @@ fn verify_view_change(
     Ok(())
 }
 
-impl<S: App, A: Addr, C: Context<Self, A>> OnErasedEvent<Recv<Verifiable<ViewChange>>, C>
-    for State<S, A>
+impl<S: App, A: Addr, P, C: Context<Self, A>> OnErasedEvent<Recv<Verifiable<ViewChange>>, C>
+    for State<S, A, P>
 {
     fn on_event(
         &mut self,
@@ -927,7 +1047,9 @@ impl<S: App, A: Addr, C: Context<Self, A>> OnErasedEvent<Recv<Verifiable<ViewCha
     }
 }
 
-impl<S: App, A: Addr, C: Context<Self, A>> OnErasedEvent<Verified<ViewChange>, C> for State<S, A> {
+impl<S: App, A: Addr, P, C: Context<Self, A>> OnErasedEvent<Verified<ViewChange>, C>
+    for State<S, A, P>
+{
     fn on_event(
         &mut self,
         Verified(view_change): Verified<ViewChange>,
@@ -980,7 +1102,7 @@ fn pre_prepares_for_view_changes(
     Ok(pre_prepares)
 }
 
-impl<S: App, A: Addr> State<S, A> {
+impl<S: App, A: Addr, P> State<S, A, P> {
     fn have_entered(&self, view_num: u32) -> bool {
         self.view_num > view_num || self.view_num == view_num && !self.view_change()
     }
@@ -993,23 +1115,20 @@ impl<S: App, A: Addr> State<S, A> {
         if self.have_entered(view_change.view_num) {
             return Ok(());
         }
-        let view_change_quorum = self.view_changes.entry(view_change.view_num).or_default();
-        if view_change_quorum.len() >= self.config.num_replica - self.config.num_faulty {
+        // the view changes keep being resent until the new view is entered, and the quorum is only
+        // acted on the first time it is reached
+        if self.view_changes.reached(&view_change.view_num) {
             return Ok(());
         }
-
-        view_change_quorum.insert(view_change.replica_id, view_change.clone());
-        // println!(
-        //     "[{}] view {} ViewChange {}",
-        //     self.id,
-        //     view_change.view_num,
-        //     quorum.len()
-        // );
-        if view_change_quorum.len() == self.config.num_replica - self.config.num_faulty {
+        let view_num = view_change.view_num;
+        if let Some(view_changes) =
+            self.view_changes
+                .insert(view_num, view_change.replica_id, view_change)
+        {
             // it is possible that i'm working on view change into view v while collecting a
             // majority that working on view change into view v' > v
-            self.view_num = view_change.view_num;
-            let view_changes = view_change_quorum.clone();
+            let view_changes = view_changes.clone();
+            self.view_num = view_num;
             if self.is_primary() {
                 let view_num = self.view_num;
                 context
@@ -1037,7 +1156,7 @@ impl<S: App, A: Addr> State<S, A> {
     }
 }
 
-impl<S: App, A: Addr, C: Context<Self, A>> OnErasedEvent<Signed<NewView>, C> for State<S, A> {
+impl<S: App, A: Addr, P, C: Context<Self, A>> OnErasedEvent<Signed<NewView>, C> for State<S, A, P> {
     fn on_event(
         &mut self,
         Signed(new_view): Signed<NewView>,
@@ -1051,7 +1170,7 @@ impl<S: App, A: Addr, C: Context<Self, A>> OnErasedEvent<Signed<NewView>, C> for
     }
 }
 
-impl<S: App, A: Addr> State<S, A> {
+impl<S: App, A: Addr, P> State<S, A, P> {
     fn enter_view(
         &mut self,
         new_view: Verifiable<NewView>,
@@ -1118,12 +1237,18 @@ impl<S: App, A: Addr> State<S, A> {
         self.view_changes = self.view_changes.split_off(&(self.view_num + 1));
 
         self.new_views.insert(self.view_num, new_view);
+        milestone!(
+            replica = self.id,
+            view_num = self.view_num,
+            op_num = self.op_num() - 1,
+            "view entered"
+        );
         Ok(())
     }
 }
 
-impl<S: App, A: Addr, C: Context<Self, A>> OnErasedEvent<Recv<Verifiable<NewView>>, C>
-    for State<S, A>
+impl<S: App, A: Addr, P, C: Context<Self, A>> OnErasedEvent<Recv<Verifiable<NewView>>, C>
+    for State<S, A, P>
 {
     fn on_event(
         &mut self,
@@ -1167,7 +1292,9 @@ impl<S: App, A: Addr, C: Context<Self, A>> OnErasedEvent<Recv<Verifiable<NewView
     }
 }
 
-impl<S: App, A: Addr, C: Context<Self, A>> OnErasedEvent<Verified<NewView>, C> for State<S, A> {
+impl<S: App, A: Addr, P, C: Context<Self, A>> OnErasedEvent<Verified<NewView>, C>
+    for State<S, A, P>
+{
     fn on_event(
         &mut self,
         Verified(new_view): Verified<NewView>,
@@ -14,7 +14,9 @@ use crate::{
 
 use super::{
     client,
-    messages::{Commit, NewView, PrePrepare, Prepare, QueryNewView, Reply, Request, ViewChange},
+    messages::{
+        Busy, Commit, NewView, PrePrepare, Prepare, QueryNewView, Reply, Request, ViewChange,
+    },
     replica::{self, PeerNet},
 };
 
@@ -30,6 +32,7 @@ impl crate::net::Addr for Addr {}
 pub enum Message {
     Request(Request<Addr>),
     Reply(Reply),
+    Busy(Busy),
     PrePrepare(Verifiable<PrePrepare>, Vec<Request<Addr>>),
     Prepare(Verifiable<Prepare>),
     Commit(Verifiable<Commit>),
@@ -102,6 +105,7 @@ where
     ) -> anyhow::Result<()> {
         match event {
             Event::Message(_, Message::Reply(message)) => self.on_event(Recv(message), context),
+            Event::Message(_, Message::Busy(message)) => self.on_event(Recv(message), context),
             Event::Timer(_, _, Timer::ClientResend) => {
                 // context.schedule.tick(id)?;
                 self.on_event(client::events::Resend, context)
@@ -200,8 +204,8 @@ where
 
 #[derive(Debug)]
 pub struct NetworkContext<'a, N> {
-    state: &'a mut N,
-    all: Vec<Addr>,
+    pub state: &'a mut N,
+    pub all: Vec<Addr>,
 }
 
 impl<N: SendMessage<Addr, M>, M: Clone> SendMessage<All, M> for NetworkContext<'_, N> {
@@ -263,7 +267,7 @@ pub struct ReplicaContext<'a, N, T> {
 
 impl<'a, N, T> replica::Context<ReplicaState, Addr> for ReplicaContext<'a, N, T>
 where
-    N: PeerNet<Addr> + SendMessage<Addr, Reply>,
+    N: PeerNet<Addr> + SendMessage<Addr, Reply> + SendMessage<Addr, Busy>,
     T: replica::Schedule,
 {
     type PeerNet = N;
@@ -545,3 +549,400 @@ mod simulate {
         }
     }
 }
+
+mod admission {
+    use std::time::Duration;
+
+    use crate::{
+        admission::QueueLimit,
+        codec::{Decode, Encode, Payload},
+        crypto::Crypto,
+        event::{
+            combinators::{erase::Transient as EraseTransient, Transient},
+            OnErasedEvent as _, Work,
+        },
+        model::search::state::{Network, Schedule},
+        net::events::Recv,
+        pbft::{
+            messages::{Busy, Request},
+            replica, PublicParameters,
+        },
+        workload::app::kvstore::{self, KVStore},
+    };
+
+    use super::{Addr, Message, NetworkContext, Timer};
+
+    type State = replica::State<kvstore::App, Addr, QueueLimit>;
+
+    struct Context<'a> {
+        net: NetworkContext<'a, Network<Addr, Message>>,
+        crypto_worker: Transient<Work<Crypto, EraseTransient<State, Self>>>,
+        schedule: Schedule<Timer>,
+    }
+
+    impl<'a> replica::Context<State, Addr> for Context<'a> {
+        type PeerNet = NetworkContext<'a, Network<Addr, Message>>;
+        type DownlinkNet = NetworkContext<'a, Network<Addr, Message>>;
+        type CryptoWorker = Transient<Work<Crypto, Self::CryptoContext>>;
+        type CryptoContext = EraseTransient<State, Self>;
+        type Schedule = Schedule<Timer>;
+        fn peer_net(&mut self) -> &mut Self::PeerNet {
+            &mut self.net
+        }
+        fn downlink_net(&mut self) -> &mut Self::DownlinkNet {
+            &mut self.net
+        }
+        fn crypto_worker(&mut self) -> &mut Self::CryptoWorker {
+            &mut self.crypto_worker
+        }
+        fn schedule(&mut self) -> &mut Self::Schedule {
+            &mut self.schedule
+        }
+    }
+
+    #[test]
+    fn busy_on_queue_limit() -> anyhow::Result<()> {
+        let config = PublicParameters {
+            num_replica: 4,
+            num_faulty: 1,
+            num_concurrent: 1,
+            max_batch_size: 1,
+            ..PublicParameters::durations(Duration::from_millis(100))
+        };
+        let mut replica = State::with_admission(
+            0,
+            Decode::json(Encode::json(KVStore::new())),
+            config,
+            QueueLimit(1),
+        );
+        let mut network = Network::new();
+        let mut context = Context {
+            net: NetworkContext {
+                state: &mut network,
+                all: (1..4).map(Addr::Replica).collect(),
+            },
+            crypto_worker: Transient::new(),
+            schedule: Schedule::new(),
+        };
+        for client_id in 0..4 {
+            let request = Request {
+                seq: 1,
+                op: Payload(Default::default()),
+                client_id,
+                client_addr: Addr::Client(client_id as _),
+                token: None,
+            };
+            replica.on_event(Recv(request), &mut context)?
+        }
+        // the first request is being proposed (the PrePrepare is pending signing), the second one
+        // is queued up, and the rest get rejected instead of queuing up further
+        anyhow::ensure!(context.crypto_worker.len() == 1);
+        drop(context);
+        let messages = network.events().collect::<Vec<_>>();
+        anyhow::ensure!(
+            matches!(
+                &messages[..],
+                [
+                    (Addr::Client(2), Message::Busy(Busy { seq: 1, .. })),
+                    (Addr::Client(3), Message::Busy(Busy { seq: 1, .. })),
+                ]
+            ),
+            "{messages:?}"
+        );
+        Ok(())
+    }
+}
+
+mod client_token {
+    use std::time::Duration;
+
+    use crate::{
+        codec::{Decode, Encode, Payload},
+        crypto::{Crypto, CryptoFlavor},
+        event::{combinators::Transient, OnErasedEvent as _},
+        model::search::state::{Network, Schedule},
+        net::events::Recv,
+        pbft::{messages::Request, PublicParameters},
+        workload::app::kvstore::KVStore,
+    };
+
+    use super::{fix_submit, Addr, Message, NetworkContext, ReplicaContext, ReplicaState, Timer};
+
+    #[test]
+    fn restarted_client() -> anyhow::Result<()> {
+        let config = PublicParameters {
+            num_replica: 4,
+            num_faulty: 1,
+            // enough room for proposing every request that is not deduplicated
+            num_concurrent: 4,
+            max_batch_size: 1,
+            ..PublicParameters::durations(Duration::from_millis(100))
+        };
+        let mut replica = ReplicaState::new(0, Decode::json(Encode::json(KVStore::new())), config);
+        let mut crypto = Crypto::new_hardcoded(4, 0u8, CryptoFlavor::Plain)?;
+        let mut network = Network::new();
+        let mut schedule = Schedule::<Timer>::new();
+        let mut context = ReplicaContext {
+            net: NetworkContext {
+                state: &mut network,
+                all: (1..4).map(Addr::Replica).collect(),
+            },
+            crypto: &mut crypto,
+            crypto_worker: Transient::new(),
+            schedule: &mut schedule,
+        };
+        for (token, seq) in [
+            (1, 5),
+            // client 0 restarts and numbers from 1 again
+            (2, 1),
+            // retransmission of the restarted client
+            (2, 1),
+            // stale and retransmitted requests of the previous incarnation
+            (1, 4),
+            (1, 5),
+        ] {
+            let request = Request {
+                seq,
+                op: Payload(Default::default()),
+                client_id: 0,
+                client_addr: Addr::Client(0),
+                token: Some(token),
+            };
+            replica.on_event(Recv(request), &mut context)?;
+            fix_submit(&mut replica, &mut context)?
+        }
+        drop(context);
+        let mut proposed = network
+            .events()
+            .filter_map(|(addr, message)| match message {
+                Message::PrePrepare(_, requests) if addr == Addr::Replica(1) => Some(requests),
+                _ => None,
+            })
+            .flatten()
+            .map(|request| (request.token, request.seq))
+            .collect::<Vec<_>>();
+        proposed.sort();
+        anyhow::ensure!(proposed == [(Some(1), 5), (Some(2), 1)], "{proposed:?}");
+        Ok(())
+    }
+}
+
+mod client_resend {
+    use std::time::Duration;
+
+    use bytes::Bytes;
+
+    use crate::{
+        codec::Payload,
+        event::OnErasedEvent as _,
+        model::search::state::{Network, Schedule},
+        net::events::Recv,
+        pbft::{
+            client,
+            messages::{Busy, Reply},
+            PublicParameters,
+        },
+        workload::events::{Invoke, InvokeOk},
+    };
+
+    use super::{Addr, Message, NetworkContext, Timer};
+
+    struct Context<'a> {
+        net: NetworkContext<'a, Network<Addr, Message>>,
+        upcall: &'a mut Option<InvokeOk<Bytes>>,
+        schedule: &'a mut Schedule<Timer>,
+    }
+
+    impl<'a> client::Context<Addr> for Context<'a> {
+        type Net = NetworkContext<'a, Network<Addr, Message>>;
+        type Upcall = Option<InvokeOk<Bytes>>;
+        type Schedule = Schedule<Timer>;
+        fn net(&mut self) -> &mut Self::Net {
+            &mut self.net
+        }
+        fn upcall(&mut self) -> &mut Self::Upcall {
+            self.upcall
+        }
+        fn schedule(&mut self) -> &mut Self::Schedule {
+            self.schedule
+        }
+    }
+
+    // a fresh network on every step, so the messages sent by each step can be inspected alone
+    fn step(
+        client: &mut client::State<Addr>,
+        upcall: &mut Option<InvokeOk<Bytes>>,
+        schedule: &mut Schedule<Timer>,
+        event: impl FnOnce(&mut client::State<Addr>, &mut Context<'_>) -> anyhow::Result<()>,
+    ) -> anyhow::Result<Vec<Addr>> {
+        let mut network = Network::new();
+        let mut context = Context {
+            net: NetworkContext {
+                state: &mut network,
+                all: (0..4).map(Addr::Replica).collect(),
+            },
+            upcall,
+            schedule,
+        };
+        event(client, &mut context)?;
+        drop(context);
+        Ok(network.events().map(|(addr, _)| addr).collect())
+    }
+
+    #[test]
+    fn without_primary() -> anyhow::Result<()> {
+        let config = PublicParameters {
+            num_replica: 4,
+            num_faulty: 1,
+            ..PublicParameters::durations(Duration::from_millis(100))
+        };
+        let mut client = client::State::new(0, Addr::Client(0), config);
+        let mut upcall = None;
+        let mut schedule = Schedule::new();
+
+        let sent = step(
+            &mut client,
+            &mut upcall,
+            &mut schedule,
+            |client, context| client.on_event(Invoke(Bytes::from("foo")), context),
+        )?;
+        anyhow::ensure!(sent == [Addr::Replica(0)]);
+        // the request to the primary is lost, or the primary ignores it
+        let sent = step(
+            &mut client,
+            &mut upcall,
+            &mut schedule,
+            |client, context| client.on_event(client::events::Resend, context),
+        )?;
+        anyhow::ensure!(sent == (0..4).map(Addr::Replica).collect::<Vec<_>>());
+
+        // the primary's reply is lost, and one of the backups replies twice
+        for replica_id in [1, 1, 2, 3] {
+            let reply = Reply {
+                seq: 1,
+                result: Payload(Bytes::from("bar")),
+                view_num: 0,
+                replica_id,
+            };
+            // the extra reply from replica 3 must not cause a duplicated delivery, which would be
+            // rejected by the `Option` upcall
+            step(
+                &mut client,
+                &mut upcall,
+                &mut schedule,
+                |client, context| client.on_event(Recv(reply), context),
+            )?;
+            anyhow::ensure!(upcall.is_some() == (replica_id != 1));
+        }
+        let Some(InvokeOk(result)) = upcall else {
+            unreachable!()
+        };
+        anyhow::ensure!(result == "bar");
+        anyhow::ensure!(schedule.events().next().is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn larger_quorum() -> anyhow::Result<()> {
+        let config = PublicParameters {
+            num_replica: 4,
+            num_faulty: 1,
+            ..PublicParameters::durations(Duration::from_millis(100))
+        };
+        anyhow::ensure!(client::State::new(0, Addr::Client(0), config.clone())
+            .with_num_reply(1)
+            .is_err());
+        anyhow::ensure!(client::State::new(0, Addr::Client(0), config.clone())
+            .with_num_reply(4)
+            .is_err());
+        // n - f, i.e. the request is known to be executed by a quorum on delivery
+        let mut client = client::State::new(0, Addr::Client(0), config).with_num_reply(3)?;
+        let mut upcall = None;
+        let mut schedule = Schedule::new();
+
+        step(
+            &mut client,
+            &mut upcall,
+            &mut schedule,
+            |client, context| client.on_event(Invoke(Bytes::from("foo")), context),
+        )?;
+        // f + 1 matching replies are not enough anymore, and the mismatched one does not count
+        for (replica_id, result, delivered) in [
+            (1, "bar", false),
+            (2, "bar", false),
+            (3, "baz", false),
+            (0, "bar", true),
+        ] {
+            let reply = Reply {
+                seq: 1,
+                result: Payload(Bytes::from(result)),
+                view_num: 0,
+                replica_id,
+            };
+            step(
+                &mut client,
+                &mut upcall,
+                &mut schedule,
+                |client, context| client.on_event(Recv(reply), context),
+            )?;
+            anyhow::ensure!(upcall.is_some() == delivered);
+        }
+        let Some(InvokeOk(result)) = upcall else {
+            unreachable!()
+        };
+        anyhow::ensure!(result == "bar");
+        anyhow::ensure!(schedule.events().next().is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn busy_from_primary_only() -> anyhow::Result<()> {
+        let config = PublicParameters {
+            num_replica: 4,
+            num_faulty: 1,
+            ..PublicParameters::durations(Duration::from_millis(100))
+        };
+        let mut client = client::State::new(0, Addr::Client(0), config);
+        let mut upcall = None;
+        let mut schedule = Schedule::new();
+
+        step(
+            &mut client,
+            &mut upcall,
+            &mut schedule,
+            |client, context| client.on_event(Invoke(Bytes::from("foo")), context),
+        )?;
+        let timers = |schedule: &Schedule<Timer>| schedule.events().map(|(id, _)| id).collect();
+        let resend_timers: Vec<_> = timers(&schedule);
+        // from a backup, and from a backup pretending to be the primary of a later view
+        for (view_num, replica_id) in [(0, 1), (1, 2)] {
+            let busy = Busy {
+                seq: 1,
+                view_num,
+                replica_id,
+            };
+            step(
+                &mut client,
+                &mut upcall,
+                &mut schedule,
+                |client, context| client.on_event(Recv(busy), context),
+            )?;
+            anyhow::ensure!(timers(&schedule) == resend_timers)
+        }
+        let busy = Busy {
+            seq: 1,
+            view_num: 0,
+            replica_id: 0,
+        };
+        step(
+            &mut client,
+            &mut upcall,
+            &mut schedule,
+            |client, context| client.on_event(Recv(busy), context),
+        )?;
+        // the resend timer is reset for backing off
+        let backoff_timers = timers(&schedule);
+        anyhow::ensure!(backoff_timers.len() == 1 && backoff_timers != resend_timers);
+        Ok(())
+    }
+}
@@ -0,0 +1,133 @@
+use std::collections::BTreeMap;
+
+use crate::crypto::Verifiable;
+
+// signed messages from distinct replicas, keyed by replica index
+pub type Quorum<M> = BTreeMap<u8, Verifiable<M>>;
+
+// the "collect enough matching signed messages" step that every phase of a quorum protocol does
+// messages are expected to be verified before inserted, and are deduplicated by sender. messages
+// under the same key are considered matching, so either put everything that has to match (view
+// number, digest, etc.) into the key, or `retain` the matching ones once knowing what to match
+// against
+// the quorum stays in the collector after reaching the threshold until it's `remove`d. this allows
+// the caller to postpone taking the certificate e.g. before the corresponding proposal arrives; the
+// following insertions keep reporting the certificate, so a caller that wants to act only once
+// should `remove` it (or stop inserting) on the first report
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct QuorumCert<K, M> {
+    threshold: usize,
+    quorums: BTreeMap<K, Quorum<M>>,
+}
+
+impl<K, M> QuorumCert<K, M> {
+    pub fn new(threshold: usize) -> Self {
+        Self {
+            threshold,
+            quorums: Default::default(),
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.quorums.clear()
+    }
+}
+
+impl<K: Ord, M> QuorumCert<K, M> {
+    // return the certificate if the quorum of `key` has reached the threshold, either by this
+    // insertion or an earlier one
+    // a duplicated insertion from the same sender replaces the previous message
+    pub fn insert(&mut self, key: K, sender: u8, message: Verifiable<M>) -> Option<&Quorum<M>> {
+        let quorum = self.quorums.entry(key).or_default();
+        quorum.insert(sender, message);
+        if quorum.len() >= self.threshold {
+            Some(quorum)
+        } else {
+            None
+        }
+    }
+
+    // whether the quorum of `key` has reached the threshold. for the callers that act only once but
+    // keep the certificate around, so stop inserting after this returns true
+    pub fn reached(&self, key: &K) -> bool {
+        self.quorums
+            .get(key)
+            .is_some_and(|quorum| quorum.len() >= self.threshold)
+    }
+
+    pub fn remove(&mut self, key: &K) -> Option<Quorum<M>> {
+        self.quorums.remove(key)
+    }
+
+    pub fn retain(&mut self, key: &K, mut f: impl FnMut(&Verifiable<M>) -> bool) {
+        if let Some(quorum) = self.quorums.get_mut(key) {
+            quorum.retain(|_, message| f(message))
+        }
+    }
+
+    // similar to `BTreeMap::split_off`, return the quorums of keys >= `key` and keep the rest
+    pub fn split_off(&mut self, key: &K) -> Self {
+        Self {
+            threshold: self.threshold,
+            quorums: self.quorums.split_off(key),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::crypto::{Crypto, CryptoFlavor};
+
+    use super::*;
+
+    fn sign(sender: u8, message: u32) -> anyhow::Result<Verifiable<u32>> {
+        Ok(Crypto::new_hardcoded(4, sender, CryptoFlavor::Plain)?.sign(message))
+    }
+
+    #[test]
+    fn threshold() -> anyhow::Result<()> {
+        let mut cert = QuorumCert::new(3);
+        assert!(cert.insert(0, 0, sign(0, 42)?).is_none());
+        assert!(cert.insert(1, 1, sign(1, 43)?).is_none());
+        assert!(cert.insert(0, 1, sign(1, 42)?).is_none());
+        let quorum = cert.insert(0, 2, sign(2, 42)?).cloned();
+        assert_eq!(
+            quorum.map(|quorum| quorum.into_keys().collect::<Vec<_>>()),
+            Some(vec![0, 1, 2])
+        );
+        assert!(cert.insert(0, 3, sign(3, 42)?).is_some());
+        assert_eq!(cert.remove(&0).map(|quorum| quorum.len()), Some(4));
+        assert!(cert.remove(&0).is_none());
+        assert!(cert.insert(1, 2, sign(2, 43)?).is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn dedup() -> anyhow::Result<()> {
+        let mut cert = QuorumCert::new(2);
+        assert!(cert.insert(0, 0, sign(0, 42)?).is_none());
+        assert!(cert.insert(0, 0, sign(0, 42)?).is_none());
+        assert!(cert.insert(0, 0, sign(0, 43)?).is_none());
+        cert.retain(&0, |message| **message == 42);
+        assert!(cert.insert(0, 1, sign(1, 42)?).is_none());
+        assert!(cert.insert(0, 2, sign(2, 42)?).is_some());
+        Ok(())
+    }
+
+    #[test]
+    fn split_off() -> anyhow::Result<()> {
+        let mut cert = QuorumCert::new(2);
+        for key in 0..3 {
+            for sender in 0..2 {
+                cert.insert(key, sender, sign(sender, 42)?);
+            }
+        }
+        let mut cert = cert.split_off(&1);
+        assert!(!cert.reached(&0));
+        assert!(cert.reached(&1));
+        assert!(cert.reached(&2));
+        assert!(!cert.reached(&3));
+        assert!(cert.insert(3, 0, sign(0, 42)?).is_none());
+        Ok(())
+    }
+}
@@ -4,9 +4,11 @@ use bytes::Bytes;
 use serde::{Deserialize, Serialize};
 
 use crate::{
+    admission::{Admission, Admit, AdmitAll, Backoff},
     codec::Payload,
     event::{ActiveTimer, OnErasedEvent, ScheduleEvent, SendEvent},
     net::{
+        combinators::Reconfigure,
         events::{Cast, Recv},
         Addr,
     },
@@ -22,6 +24,9 @@ pub struct Request<A> {
     op: Payload,
     client_id: u32,
     client_addr: A,
+    // see `pbft::messages::Request`. the requests without token are still decoded from the legacy
+    // layout, see `codec::server_decode`
+    token: Option<u64>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
@@ -30,10 +35,17 @@ pub struct Reply {
     result: Payload,
 }
 
+// the server is not admitting the request for now, see `crate::admission::Admission`
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct Busy {
+    seq: u32,
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct ClientState<A> {
     id: u32,
     addr: A,
+    token: Option<u64>,
     seq: u32,
     outstanding: Option<Outstanding>,
 }
@@ -42,17 +54,35 @@ pub struct ClientState<A> {
 struct Outstanding {
     op: Payload,
     timer: ActiveTimer,
+    backoff: Backoff,
 }
 
+const RESEND_INTERVAL: Duration = Duration::from_millis(100);
+
 impl<A> ClientState<A> {
     pub fn new(id: u32, addr: A) -> Self {
         Self {
             id,
             addr,
+            token: None,
             seq: 0,
             outstanding: Default::default(),
         }
     }
+
+    pub fn with_token(self, token: u64) -> Self {
+        Self {
+            token: Some(token),
+            ..self
+        }
+    }
+
+    // (id, addr, token, seq), for carrying on with pbft under the same identity, see
+    // `pbft::client::State::from_unreplicated`
+    pub(crate) fn into_parts(self) -> anyhow::Result<(u32, A, Option<u64>, u32)> {
+        anyhow::ensure!(self.outstanding.is_none(), "outstanding invocation");
+        Ok((self.id, self.addr, self.token, self.seq))
+    }
 }
 
 pub mod client {
@@ -74,9 +104,8 @@ impl<A: Addr, C: ClientContext<A>> OnErasedEvent<Invoke<Bytes>, C> for ClientSta
         self.seq += 1;
         let replaced = self.outstanding.replace(Outstanding {
             op: Payload(op),
-            timer: context
-                .schedule()
-                .set(Duration::from_millis(100), client::Resend)?,
+            timer: context.schedule().set(RESEND_INTERVAL, client::Resend)?,
+            backoff: Default::default(),
         });
         anyhow::ensure!(replaced.is_none());
         self.send_request(context)
@@ -88,6 +117,7 @@ impl<A: Addr> ClientState<A> {
         let request = Request {
             client_id: self.id,
             client_addr: self.addr.clone(),
+            token: self.token,
             seq: self.seq,
             op: self
                 .outstanding
@@ -114,74 +144,177 @@ impl<A, C: ClientContext<A>> OnErasedEvent<Recv<Reply>, C> for ClientState<A> {
     }
 }
 
+// see `pbft::client` for the backing off
+impl<A, C: ClientContext<A>> OnErasedEvent<Recv<Busy>, C> for ClientState<A> {
+    fn on_event(&mut self, Recv(busy): Recv<Busy>, context: &mut C) -> anyhow::Result<()> {
+        if busy.seq != self.seq {
+            return Ok(());
+        }
+        let Some(outstanding) = self.outstanding.as_mut() else {
+            return Ok(());
+        };
+        let Some(interval) = outstanding.backoff.busy(RESEND_INTERVAL) else {
+            return Ok(());
+        };
+        context.schedule().unset(outstanding.timer.clone())?;
+        outstanding.timer = context.schedule().set(interval, client::Resend)?;
+        Ok(())
+    }
+}
+
 impl<A: Addr, C: ClientContext<A>> OnErasedEvent<client::Resend, C> for ClientState<A> {
     fn on_event(&mut self, client::Resend: client::Resend, context: &mut C) -> anyhow::Result<()> {
         // TODO log
+        self.outstanding.as_mut().unwrap().backoff.resend();
         self.send_request(context)
     }
 }
 
+impl<A, C: ClientContext<A>> OnErasedEvent<Reconfigure<(), A>, C> for ClientState<A>
+where
+    C::Net: SendEvent<Reconfigure<(), A>>,
+{
+    fn on_event(&mut self, event: Reconfigure<(), A>, context: &mut C) -> anyhow::Result<()> {
+        context.net().send(event)
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
-pub struct ServerState<S> {
-    replies: BTreeMap<u32, Reply>,
+pub struct ServerState<S, P = AdmitAll> {
+    replies: BTreeMap<(u32, Option<u64>), Reply>, // (client id, token) -> reply
     app: S,
+    admission: P,
 }
 
 impl<S> ServerState<S> {
     pub fn new(app: S) -> Self {
+        Self::with_admission(app, AdmitAll)
+    }
+}
+
+impl<S, P> ServerState<S, P> {
+    pub fn with_admission(app: S, admission: P) -> Self {
         Self {
             app,
             replies: Default::default(),
+            admission,
         }
     }
+
+    // the app and the ((client id, token), seq, result) of the latest executed requests, see
+    // `pbft::replica::State::from_unreplicated`
+    #[allow(clippy::type_complexity)]
+    pub(crate) fn into_parts(
+        self,
+    ) -> (S, impl Iterator<Item = ((u32, Option<u64>), u32, Payload)>) {
+        let replies = self
+            .replies
+            .into_iter()
+            .map(|(key, reply)| (key, reply.seq, reply.result));
+        (self.app, replies)
+    }
 }
 
 pub trait ServerContext<A> {
-    type Net: SendEvent<Cast<A, Reply>>;
+    type Net: SendEvent<Cast<A, Reply>> + SendEvent<Cast<A, Busy>>;
     fn net(&mut self) -> &mut Self::Net;
 }
 
-impl<S: App, A, C: ServerContext<A>> OnErasedEvent<Recv<Request<A>>, C> for ServerState<S> {
+impl<S: App, A, P: Admission, C: ServerContext<A>> OnErasedEvent<Recv<Request<A>>, C>
+    for ServerState<S, P>
+{
     fn on_event(&mut self, Recv(request): Recv<Request<A>>, context: &mut C) -> anyhow::Result<()> {
-        match self.replies.get(&request.client_id) {
+        let key = (request.client_id, request.token);
+        match self.replies.get(&key) {
             Some(reply) if reply.seq > request.seq => return Ok(()),
             Some(reply) if reply.seq == request.seq => {
                 return context.net().send(Cast(request.client_addr, reply.clone()))
             }
             _ => {}
         }
+        // requests are executed on arrival, nothing is queued
+        match self.admission.admit(request.client_id, 0) {
+            Admit::Accept => {}
+            Admit::Reject => {
+                let busy = Busy { seq: request.seq };
+                return context.net().send(Cast(request.client_addr, busy));
+            }
+            Admit::Defer => return Ok(()),
+        }
         let reply = Reply {
             seq: request.seq,
             result: Payload(self.app.execute(&request.op)?),
         };
-        self.replies.insert(request.client_id, reply.clone());
+        self.replies.insert(key, reply.clone());
         context.net().send(Cast(request.client_addr, reply))
     }
 }
 
 pub mod codec {
+    use derive_more::From;
+
     use crate::codec::{bincode, Encode};
 
     use super::*;
 
+    // a breaking change of the client-bound wire format just like `pbft::messages::codec::ToClient`:
+    // the replies were sent bare before `Busy` was added
+    #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize, From)]
+    pub enum ToClient {
+        Reply(Reply),
+        Busy(Busy),
+    }
+
     pub fn client_encode<A: Addr, N>(net: N) -> Encode<Request<A>, N> {
         Encode::bincode(net)
     }
 
     pub fn client_decode<'a>(
-        mut sender: impl SendEvent<Recv<Reply>> + 'a,
+        mut sender: impl SendEvent<Recv<Reply>> + SendEvent<Recv<Busy>> + 'a,
     ) -> impl FnMut(&[u8]) -> anyhow::Result<()> + 'a {
-        move |buf| sender.send(Recv(bincode::decode(buf)?))
+        use ToClient::*;
+        move |buf| match bincode::decode(buf)? {
+            Reply(message) => sender.send(Recv(message)),
+            Busy(message) => sender.send(Recv(message)),
+        }
     }
 
-    pub fn server_encode<N>(net: N) -> Encode<Reply, N> {
+    pub fn server_encode<N>(net: N) -> Encode<ToClient, N> {
         Encode::bincode(net)
     }
 
+    // the `Request` layout before `token` was added
+    #[derive(Deserialize)]
+    struct LegacyRequest<A> {
+        seq: u32,
+        op: Payload,
+        client_id: u32,
+        client_addr: A,
+    }
+
+    // the legacy layout is a prefix of the current one, so the encoding from a client built before
+    // `Request::token` was added runs out of bytes when decoded as the current layout, and gets
+    // decoded again as the legacy one. the other way around, the older servers take the current
+    // layout as the legacy one and ignore the token, since trailing bytes are allowed
     pub fn server_decode<'a, A: Addr>(
         mut sender: impl SendEvent<Recv<Request<A>>> + 'a,
     ) -> impl FnMut(&[u8]) -> anyhow::Result<()> + 'a {
-        move |buf| sender.send(Recv(bincode::decode(buf)?))
+        move |buf| {
+            let request = match bincode::decode(buf) {
+                Ok(request) => request,
+                Err(_) => {
+                    let request = bincode::decode::<LegacyRequest<A>>(buf)?;
+                    Request {
+                        seq: request.seq,
+                        op: request.op,
+                        client_id: request.client_id,
+                        client_addr: request.client_addr,
+                        token: None,
+                    }
+                }
+            };
+            sender.send(Recv(request))
+        }
     }
 }
 
@@ -212,6 +345,7 @@ pub mod model {
     pub enum Message {
         Request(super::Request<Addr>),
         Reply(super::Reply),
+        Busy(super::Busy),
     }
 
     #[derive(Debug, Clone, PartialEq, Eq, Hash)]
@@ -296,6 +430,9 @@ pub mod model {
                         Event::Message(_, Message::Reply(message)) => {
                             client.on_event(Recv(message), &mut context)
                         }
+                        Event::Message(_, Message::Busy(message)) => {
+                            client.on_event(Recv(message), &mut context)
+                        }
                         Event::Timer(_, id, Timer::ClientResend) => {
                             context.0.schedule.tick(id)?;
                             client.on_event(client::Resend, &mut context)
@@ -383,3 +520,188 @@ pub mod model {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use crate::{
+        codec::{bincode, json, Decode, Encode},
+        model::search::state::Network,
+        workload::app::kvstore::{self, KVStore, Op},
+    };
+
+    use super::{
+        model::{Addr, Message},
+        *,
+    };
+
+    #[test]
+    fn restarted_client() -> anyhow::Result<()> {
+        let mut server = ServerState::new(Decode::json(Encode::json(KVStore::new())));
+        // a fresh network on every step, since the network state deduplicates identical messages
+        let mut step = |token, seq| -> anyhow::Result<Vec<(Addr, Message)>> {
+            let request = Request {
+                seq,
+                op: Payload(json::encode(&Op::Append("foo".into(), "x".into()))?),
+                client_id: 0,
+                client_addr: Addr::Client(0),
+                token: Some(token),
+            };
+            let mut network = Network::new();
+            server.on_event(Recv(request), &mut network)?;
+            Ok(network.events().collect())
+        };
+        let reply = |seq, value: &str| -> anyhow::Result<_> {
+            let result = kvstore::Result::AppendResult(value.into());
+            Ok(vec![(
+                Addr::Client(0),
+                Message::Reply(Reply {
+                    seq,
+                    result: Payload(json::encode(&result)?),
+                }),
+            )])
+        };
+        anyhow::ensure!(step(1, 1)? == reply(1, "x")?);
+        // retransmission gets the cached reply instead of appending again
+        anyhow::ensure!(step(1, 1)? == reply(1, "x")?);
+        // the client restarts and numbers from 1 again, which is executed despite the same seq
+        anyhow::ensure!(step(2, 1)? == reply(1, "xx")?);
+        // the previous incarnation is still deduplicated on its own
+        anyhow::ensure!(step(1, 1)? == reply(1, "x")?);
+        anyhow::ensure!(step(2, 1)? == reply(1, "xx")?);
+        Ok(())
+    }
+
+    #[test]
+    fn legacy_request() -> anyhow::Result<()> {
+        // the `Request` of before adding `token`
+        #[derive(Serialize)]
+        struct LegacyRequest {
+            seq: u32,
+            op: Payload,
+            client_id: u32,
+            client_addr: SocketAddr,
+        }
+
+        let client_addr = SocketAddr::from(([127, 0, 0, 1], 10000));
+        let legacy_request = LegacyRequest {
+            seq: 1,
+            op: Payload(Bytes::from("foo")),
+            client_id: 42,
+            client_addr,
+        };
+        let mut request = Request {
+            seq: 1,
+            op: Payload(Bytes::from("foo")),
+            client_id: 42,
+            client_addr,
+            token: None,
+        };
+        let mut received = None;
+        codec::server_decode(&mut received)(&bincode::encode(&legacy_request)?)?;
+        anyhow::ensure!(matches!(received.take(), Some(Recv(received)) if received == request));
+        request.token = Some(1);
+        codec::server_decode(&mut received)(&bincode::encode(&request)?)?;
+        anyhow::ensure!(matches!(received.take(), Some(Recv(received)) if received == request));
+        Ok(())
+    }
+
+    #[test]
+    fn upgrade() -> anyhow::Result<()> {
+        use crate::{
+            crypto::{Crypto, CryptoFlavor},
+            event::{combinators::Transient, ActiveTimer},
+            model::search::state::Schedule,
+            pbft::{
+                self,
+                tests::{NetworkContext, ReplicaContext},
+                PublicParameters,
+            },
+        };
+
+        let op = Payload(json::encode(&Op::Append("foo".into(), "x".into()))?);
+        let mut server = ServerState::new(Decode::json(Encode::json(KVStore::new())));
+        let request = Request {
+            seq: 1,
+            op: op.clone(),
+            client_id: 0,
+            client_addr: Addr::Client(0),
+            token: None,
+        };
+        server.on_event(Recv(request), &mut Network::new())?;
+
+        let config = PublicParameters {
+            num_replica: 4,
+            num_faulty: 1,
+            num_concurrent: 1,
+            max_batch_size: 1,
+            ..PublicParameters::durations(Duration::from_millis(100))
+        };
+        // a backup, which would relay the requests that are not in the client table to the primary
+        let mut replica = pbft::replica::State::from_unreplicated(1, server, config.clone());
+        let mut crypto = Crypto::new_hardcoded(4, 1u8, CryptoFlavor::Plain)?;
+        let mut schedule = Schedule::<pbft::tests::Timer>::new();
+        let mut step = |seq| -> anyhow::Result<_> {
+            let request = pbft::messages::Request {
+                seq,
+                op: op.clone(),
+                client_id: 0,
+                client_addr: pbft::tests::Addr::Client(0),
+                token: None,
+            };
+            let mut network = Network::new();
+            let mut context = ReplicaContext {
+                net: NetworkContext {
+                    state: &mut network,
+                    all: [0, 2, 3].map(pbft::tests::Addr::Replica).into(),
+                },
+                crypto: &mut crypto,
+                crypto_worker: Transient::new(),
+                schedule: &mut schedule,
+            };
+            replica.on_event(Recv(request), &mut context)?;
+            drop(context);
+            Ok(network.events().collect::<Vec<_>>())
+        };
+        // the retransmission of the request executed by the server is answered with the cached
+        // result, instead of getting executed again
+        let messages = step(1)?;
+        let result = Payload(json::encode(&kvstore::Result::AppendResult("x".into()))?);
+        anyhow::ensure!(
+            matches!(
+                &messages[..],
+                [(pbft::tests::Addr::Client(0), pbft::tests::Message::Reply(reply))]
+                    if reply.seq == 1 && reply.result == result && reply.replica_id == 1
+            ),
+            "{messages:?}"
+        );
+        let messages = step(2)?;
+        anyhow::ensure!(
+            matches!(
+                &messages[..],
+                [(
+                    pbft::tests::Addr::Replica(0),
+                    pbft::tests::Message::Request(_)
+                )]
+            ),
+            "{messages:?}"
+        );
+
+        let client = ClientState::new(0, Addr::Client(0));
+        anyhow::ensure!(
+            pbft::client::State::from_unreplicated(client.clone(), config.clone()).is_ok()
+        );
+        let client = ClientState {
+            seq: 1,
+            outstanding: Some(Outstanding {
+                op,
+                timer: ActiveTimer(0),
+                backoff: Default::default(),
+            }),
+            ..client
+        };
+        anyhow::ensure!(pbft::client::State::from_unreplicated(client, config).is_err());
+        Ok(())
+    }
+}
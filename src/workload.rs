@@ -15,13 +15,16 @@ pub mod app {
     pub mod kvstore;
 }
 
+pub mod clients;
+pub mod cluster;
 pub mod combinators;
+pub mod servers;
 
 pub trait App {
     fn execute(&mut self, op: &[u8]) -> anyhow::Result<Bytes>;
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Null;
 
 impl App for Null {
@@ -1,28 +1,27 @@
 use std::{future::Future, net::SocketAddr, sync::Arc};
 
 use bytes::Bytes;
-use neatworks::{
+use rand::random;
+use tokio::{
+    net::UdpSocket,
+    select,
+    sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
+};
+
+use crate::{
     codec::Encode,
     event::{
-        task::{self, run_with_schedule, ScheduleState},
+        task::{self, forward, run_until, run_with_schedule, ScheduleState},
         Erase, SendEvent, Untyped,
     },
     net::{
-        combinators::{Forward, IndexNet},
+        combinators::{Forward, IndexNet, Reconfigure},
         task::udp,
     },
     pbft::{self, PublicParameters},
     unreplicated,
     workload::events::{Invoke, InvokeOk},
 };
-use rand::random;
-use tokio::{
-    net::UdpSocket,
-    select,
-    sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
-};
-
-use super::util::run_until;
 
 pub trait InvokeTask {
     fn run(
@@ -32,7 +31,11 @@ pub trait InvokeTask {
     ) -> impl Future<Output = anyhow::Result<()>>;
 }
 
-pub async fn unreplicated(invoke_task: impl InvokeTask) -> anyhow::Result<()> {
+pub async fn unreplicated(
+    invoke_task: impl InvokeTask,
+    server_addr: SocketAddr,
+    mut control: UnboundedReceiver<Reconfigure<(), SocketAddr>>,
+) -> anyhow::Result<()> {
     let socket = Arc::new(UdpSocket::bind("localhost:0").await?);
     let addr = socket.local_addr()?;
     let (upcall_sender, upcall_receiver) = unbounded_channel::<InvokeOk<_>>();
@@ -63,10 +66,7 @@ pub async fn unreplicated(invoke_task: impl InvokeTask) -> anyhow::Result<()> {
         }
     }
     let mut context = Context {
-        net: unreplicated::codec::client_encode(Forward(
-            ([127, 0, 0, 1], 3000).into(),
-            socket.clone(),
-        )),
+        net: unreplicated::codec::client_encode(Forward(server_addr, socket.clone())),
         upcall: upcall_sender,
         schedule: Erase::new(ScheduleState::new(schedule_sender)),
     };
@@ -81,6 +81,10 @@ pub async fn unreplicated(invoke_task: impl InvokeTask) -> anyhow::Result<()> {
         &socket,
         unreplicated::codec::client_decode(Erase::new(sender.clone())),
     );
+    let control_task = forward(
+        &mut control,
+        task::erase::Sender::<S, Context>::new(sender.clone()),
+    );
 
     run_until(
         invoke_task.run(Erase::new(sender), upcall_receiver),
@@ -88,6 +92,7 @@ pub async fn unreplicated(invoke_task: impl InvokeTask) -> anyhow::Result<()> {
             select! {
                 result = net_task => result,
                 result = client_task => result,
+                result = control_task => result,
             }
         },
     )
@@ -98,6 +103,7 @@ pub async fn pbft(
     invoke_task: impl InvokeTask,
     config: PublicParameters,
     replica_addrs: Vec<SocketAddr>,
+    mut control: UnboundedReceiver<Reconfigure<u8, SocketAddr>>,
 ) -> anyhow::Result<()> {
     let socket = Arc::new(UdpSocket::bind("localhost:0").await?);
     let addr = socket.local_addr()?;
@@ -149,6 +155,10 @@ pub async fn pbft(
         &socket,
         pbft::messages::codec::to_client_decode(Erase::new(sender.clone())),
     );
+    let control_task = forward(
+        &mut control,
+        task::erase::Sender::<S, Context>::new(sender.clone()),
+    );
 
     run_until(
         invoke_task.run(Erase::new(sender), upcall_receiver),
@@ -156,6 +166,7 @@ pub async fn pbft(
             select! {
                 result = net_task => result,
                 result = client_task => result,
+                result = control_task => result,
             }
         },
     )
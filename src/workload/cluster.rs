@@ -0,0 +1,370 @@
+use std::{future::Future, net::SocketAddr};
+
+use tokio::{
+    net::UdpSocket,
+    sync::{
+        mpsc::{unbounded_channel, UnboundedSender},
+        oneshot,
+    },
+    task::JoinSet,
+};
+
+use crate::{
+    event::{task::run_until, SendEvent},
+    net::combinators::Reconfigure,
+    pbft::{self, PublicParameters},
+    workload::Null,
+};
+
+use super::servers::{self, Handover};
+
+// a whole cluster of servers in the current process (and runtime), communicating over loopback
+// network. for trying out the protocols without a deployment, and for sanity checking end to end
+//
+// the servers are spawned as soon as the cluster is created, and owned by the cluster i.e. get
+// aborted when it is dropped
+// the ports are picked by the OS, so multiple clusters can coexist in one process, e.g. in tests
+#[derive(Debug)]
+pub struct Cluster {
+    pub addrs: Vec<SocketAddr>,
+    pub control: Control,
+    servers: JoinSet<anyhow::Result<()>>,
+    // only for the unreplicated cluster, see `upgrade`
+    handover: Option<oneshot::Sender<Handover>>,
+}
+
+// broadcast the control events to all servers of the cluster
+// e.g. `Reconfigure(index, addr)` after the server of `index` is moved to `addr`, with the clients
+// notified separately
+// empty for the unreplicated cluster: the single server replies to the address carried by each
+// request, and there are no peers to notify. only the clients need `Reconfigure` (through their own
+// control) when the server moves
+#[derive(Debug, Clone)]
+pub struct Control(Vec<UnboundedSender<Reconfigure<u8, SocketAddr>>>);
+
+impl SendEvent<Reconfigure<u8, SocketAddr>> for Control {
+    fn send(&mut self, event: Reconfigure<u8, SocketAddr>) -> anyhow::Result<()> {
+        for sender in &mut self.0 {
+            SendEvent::send(sender, event.clone())?
+        }
+        Ok(())
+    }
+}
+
+// see `event::task::Credit::new`
+fn check_recv_bound(recv_bound: usize) -> anyhow::Result<()> {
+    anyhow::ensure!(recv_bound > 0, "receive bound must be positive");
+    Ok(())
+}
+
+impl Cluster {
+    pub async fn unreplicated(recv_bound: usize) -> anyhow::Result<Self> {
+        check_recv_bound(recv_bound)?;
+        let socket = UdpSocket::bind(SocketAddr::from(([127, 0, 0, 1], 0))).await?;
+        let addrs = vec![socket.local_addr()?];
+        let mut servers = JoinSet::new();
+        let (handover, control) = oneshot::channel();
+        servers.spawn(servers::unreplicated(socket, recv_bound, control));
+        Ok(Self {
+            addrs,
+            control: Control(Default::default()),
+            servers,
+            handover: Some(handover),
+        })
+    }
+
+    pub async fn pbft(config: &PublicParameters, recv_bound: usize) -> anyhow::Result<Self> {
+        check_recv_bound(recv_bound)?;
+        let mut cluster = Self {
+            addrs: Default::default(),
+            control: Control(Default::default()),
+            servers: JoinSet::new(),
+            handover: None,
+        };
+        cluster
+            .spawn_pbft(config, recv_bound, |id| {
+                pbft::replica::State::new(id, Null, config.clone())
+            })
+            .await?;
+        Ok(cluster)
+    }
+
+    // the unreplicated-to-replicated transition of an unreplicated cluster: the server stops and
+    // hands over its state, and pbft replicas are started on it. `addrs` and `control` are for the
+    // replicas afterwards
+    // the clients should move on to the replicas. the requests they have sent to the server are
+    // deduplicated by the replicas if retransmitted, see `pbft::replica::State::from_unreplicated`
+    pub async fn upgrade(
+        &mut self,
+        config: &PublicParameters,
+        recv_bound: usize,
+    ) -> anyhow::Result<()> {
+        // before the server stops
+        check_recv_bound(recv_bound)?;
+        let Some(handover) = self.handover.take() else {
+            anyhow::bail!("not an unreplicated cluster")
+        };
+        let (sender, receiver) = oneshot::channel();
+        if handover.send(Handover(sender)).is_err() {
+            anyhow::bail!("unreplicated server is gone")
+        }
+        let server = receiver.await?;
+        // the server returns after handing over
+        if let Some(result) = self.servers.join_next().await {
+            result??
+        }
+        self.spawn_pbft(config, recv_bound, |id| {
+            pbft::replica::State::from_unreplicated(id, server.clone(), config.clone())
+        })
+        .await
+    }
+
+    async fn spawn_pbft(
+        &mut self,
+        config: &PublicParameters,
+        recv_bound: usize,
+        mut replica: impl FnMut(u8) -> pbft::replica::State<Null, SocketAddr>,
+    ) -> anyhow::Result<()> {
+        // distinct loopback IPs, so the addresses stay the same shape as a real deployment where
+        // replicas are on different hosts
+        let mut sockets = Vec::new();
+        for index in 0..config.num_replica {
+            let Ok(host) = u8::try_from(index + 1) else {
+                anyhow::bail!("no loopback address for replica {index}")
+            };
+            sockets.push(UdpSocket::bind(SocketAddr::from(([127, 0, 0, host], 0))).await?)
+        }
+        self.addrs = sockets
+            .iter()
+            .map(UdpSocket::local_addr)
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut control = Vec::new();
+        for (index, socket) in sockets.into_iter().enumerate() {
+            let (control_sender, control_receiver) = unbounded_channel();
+            control.push(control_sender);
+            self.servers.spawn(servers::pbft(
+                config.clone(),
+                index,
+                // the index is checked to fit above
+                replica(index as _),
+                socket,
+                self.addrs.clone(),
+                recv_bound,
+                control_receiver,
+            ));
+        }
+        self.control = Control(control);
+        Ok(())
+    }
+
+    // run `task` (usually a client) against the cluster until it finishes, or fail early if any of
+    // the servers fails
+    pub async fn run(
+        &mut self,
+        task: impl Future<Output = anyhow::Result<()>>,
+    ) -> anyhow::Result<()> {
+        run_until(task, async {
+            let Some(result) = self.servers.join_next().await else {
+                anyhow::bail!("empty cluster")
+            };
+            result?
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering::SeqCst},
+            Arc,
+        },
+        time::Duration,
+    };
+
+    use bytes::Bytes;
+    use tokio::{spawn, sync::mpsc::UnboundedReceiver};
+
+    use crate::workload::{
+        clients::{self, InvokeTask},
+        events::{Invoke, InvokeOk},
+    };
+
+    use super::*;
+
+    const RECV_BOUND: usize = 1 << 10;
+
+    struct Invokes(usize);
+
+    impl InvokeTask for Invokes {
+        async fn run(
+            self,
+            mut sender: impl SendEvent<Invoke<Bytes>>,
+            mut receiver: UnboundedReceiver<InvokeOk<Bytes>>,
+        ) -> anyhow::Result<()> {
+            for _ in 0..self.0 {
+                sender.send(Invoke(Default::default()))?;
+                anyhow::ensure!(receiver.recv().await.is_some())
+            }
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn unreplicated() -> anyhow::Result<()> {
+        let mut cluster = Cluster::unreplicated(RECV_BOUND).await?;
+        let client_task =
+            clients::unreplicated(Invokes(10), cluster.addrs[0], unbounded_channel().1);
+        cluster.run(client_task).await
+    }
+
+    fn pbft_config() -> PublicParameters {
+        PublicParameters {
+            num_replica: 4,
+            num_faulty: 1,
+            num_concurrent: 1,
+            max_batch_size: 1,
+            ..PublicParameters::durations(Duration::from_millis(300))
+        }
+    }
+
+    #[tokio::test]
+    async fn pbft() -> anyhow::Result<()> {
+        let config = pbft_config();
+        let mut cluster = Cluster::pbft(&config, RECV_BOUND).await?;
+        let client_task = clients::pbft(
+            Invokes(3),
+            config,
+            cluster.addrs.clone(),
+            unbounded_channel().1,
+        );
+        cluster.run(client_task).await
+    }
+
+    // `swap` before the second invocation
+    struct InvokesWithSwap<F> {
+        num_invoke: usize,
+        swap: F,
+    }
+
+    impl<F: FnMut() -> anyhow::Result<()>> InvokeTask for InvokesWithSwap<F> {
+        async fn run(
+            mut self,
+            mut sender: impl SendEvent<Invoke<Bytes>>,
+            mut receiver: UnboundedReceiver<InvokeOk<Bytes>>,
+        ) -> anyhow::Result<()> {
+            for i in 0..self.num_invoke {
+                if i == 1 {
+                    (self.swap)()?
+                }
+                sender.send(Invoke(Default::default()))?;
+                anyhow::ensure!(receiver.recv().await.is_some())
+            }
+            Ok(())
+        }
+    }
+
+    async fn relay(
+        socket: UdpSocket,
+        addr: SocketAddr,
+        num_relayed: Arc<AtomicUsize>,
+    ) -> anyhow::Result<()> {
+        let mut buf = vec![0; 64 << 10];
+        loop {
+            let (len, _) = socket.recv_from(&mut buf).await?;
+            socket.send_to(&buf[..len], addr).await?;
+            num_relayed.fetch_add(1, SeqCst);
+        }
+    }
+
+    #[tokio::test]
+    async fn unreplicated_swap_addr() -> anyhow::Result<()> {
+        let mut cluster = Cluster::unreplicated(RECV_BOUND).await?;
+        // the server is moved to the relay's address
+        let socket = UdpSocket::bind(SocketAddr::from(([127, 0, 0, 1], 0))).await?;
+        let relay_addr = socket.local_addr()?;
+        let num_relayed = Arc::new(AtomicUsize::new(0));
+        let relay_task = spawn(relay(socket, cluster.addrs[0], num_relayed.clone()));
+        let (mut client_control, control_receiver) = unbounded_channel();
+        let invoke_task = InvokesWithSwap {
+            num_invoke: 3,
+            swap: move || SendEvent::send(&mut client_control, Reconfigure((), relay_addr)),
+        };
+        let client_task = clients::unreplicated(invoke_task, cluster.addrs[0], control_receiver);
+        cluster.run(client_task).await?;
+        relay_task.abort();
+        anyhow::ensure!(num_relayed.load(SeqCst) > 0);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn pbft_swap_addr() -> anyhow::Result<()> {
+        let config = pbft_config();
+        let mut cluster = Cluster::pbft(&config, RECV_BOUND).await?;
+        // replica 0 (the primary) is moved to the relay's address, i.e. from now on it is only
+        // reachable through the relay
+        let socket = UdpSocket::bind(SocketAddr::from(([127, 0, 0, 1], 0))).await?;
+        let relay_addr = socket.local_addr()?;
+        let replica_addr = cluster.addrs[0];
+        let num_relayed = Arc::new(AtomicUsize::new(0));
+        let relay_task = spawn(relay(socket, replica_addr, num_relayed.clone()));
+        let (mut client_control, control_receiver) = unbounded_channel();
+        let mut control = cluster.control.clone();
+        let invoke_task = InvokesWithSwap {
+            num_invoke: 3,
+            swap: move || {
+                control.send(Reconfigure(0, relay_addr))?;
+                SendEvent::send(&mut client_control, Reconfigure(0, relay_addr))
+            },
+        };
+        let client_task =
+            clients::pbft(invoke_task, config, cluster.addrs.clone(), control_receiver);
+        cluster.run(client_task).await?;
+        relay_task.abort();
+        anyhow::ensure!(num_relayed.load(SeqCst) > 0);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn upgrade() -> anyhow::Result<()> {
+        let mut cluster = Cluster::unreplicated(RECV_BOUND).await?;
+        let client_task =
+            clients::unreplicated(Invokes(3), cluster.addrs[0], unbounded_channel().1);
+        cluster.run(client_task).await?;
+        let config = pbft_config();
+        cluster.upgrade(&config, RECV_BOUND).await?;
+        anyhow::ensure!(cluster.addrs.len() == config.num_replica);
+        let client_task = clients::pbft(
+            Invokes(3),
+            config.clone(),
+            cluster.addrs.clone(),
+            unbounded_channel().1,
+        );
+        cluster.run(client_task).await?;
+        anyhow::ensure!(cluster.upgrade(&config, RECV_BOUND).await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn zero_recv_bound() -> anyhow::Result<()> {
+        anyhow::ensure!(Cluster::unreplicated(0).await.is_err());
+        anyhow::ensure!(Cluster::pbft(&pbft_config(), 0).await.is_err());
+        let mut cluster = Cluster::unreplicated(RECV_BOUND).await?;
+        anyhow::ensure!(cluster.upgrade(&pbft_config(), 0).await.is_err());
+        // still serving
+        let client_task =
+            clients::unreplicated(Invokes(1), cluster.addrs[0], unbounded_channel().1);
+        cluster.run(client_task).await
+    }
+
+    #[tokio::test]
+    async fn too_many_replicas() {
+        let config = PublicParameters {
+            num_replica: 256,
+            num_faulty: 85,
+            ..PublicParameters::durations(Duration::from_millis(300))
+        };
+        assert!(Cluster::pbft(&config, RECV_BOUND).await.is_err())
+    }
+}
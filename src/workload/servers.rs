@@ -1,23 +1,46 @@
 use std::{net::SocketAddr, sync::Arc};
 
-use neatworks::{
+use tokio::{
+    net::UdpSocket,
+    select,
+    sync::{
+        mpsc::{unbounded_channel, UnboundedReceiver},
+        oneshot,
+    },
+};
+
+use crate::{
     codec::Encode,
     crypto::{Crypto, CryptoFlavor},
     event::{
-        task::{self, run, run_with_schedule, run_worker, ScheduleState},
+        task::{self, forward, run, run_with_schedule, run_worker, Bounded, Credit, ScheduleState},
         Erase, Untyped,
     },
-    net::{combinators::IndexNet, task::udp},
+    net::{
+        combinators::{IndexNet, Reconfigure},
+        task::udp,
+    },
     pbft, unreplicated,
     workload::Null,
 };
-use tokio::{net::UdpSocket, select, sync::mpsc::unbounded_channel};
 
-pub async fn unreplicated() -> anyhow::Result<()> {
-    let socket = Arc::new(UdpSocket::bind("localhost:3000").await?);
+// stop serving and hand over the server state, for the unreplicated-to-replicated transition, see
+// `pbft::replica::State::from_unreplicated`
+// the received requests that are not processed yet are dropped, as if they were lost in the
+// network, and get resent to the replicas
+#[derive(Debug)]
+pub struct Handover(pub oneshot::Sender<unreplicated::ServerState<Null>>);
+
+// return after handing over, or keep serving forever if `control` is dropped without handover
+pub async fn unreplicated(
+    socket: UdpSocket,
+    recv_bound: usize,
+    control: oneshot::Receiver<Handover>,
+) -> anyhow::Result<()> {
+    let socket = Arc::new(socket);
     let (sender, mut receiver) = unbounded_channel();
 
-    type Net = Encode<unreplicated::Reply, Arc<UdpSocket>>;
+    type Net = Encode<unreplicated::codec::ToClient, Arc<UdpSocket>>;
     struct Context(Net);
     impl unreplicated::ServerContext<SocketAddr> for Context {
         type Net = Net;
@@ -26,19 +49,24 @@ pub async fn unreplicated() -> anyhow::Result<()> {
         }
     }
     let mut context = Context(unreplicated::codec::server_encode(socket.clone()));
-    let server_task = run(
-        Untyped::new(unreplicated::ServerState::new(Null)),
-        &mut context,
-        &mut receiver,
-    );
-    let net_task = udp::run(
+    let mut state = Untyped::new(unreplicated::ServerState::new(Null));
+    let server_task = run(&mut state, &mut context, &mut receiver);
+    let credit = Credit::new(recv_bound);
+    let net_task = udp::run_with_credit(
         &socket,
-        unreplicated::codec::server_decode(Erase::new(sender)),
+        &credit,
+        unreplicated::codec::server_decode(Erase::new(Bounded(sender, credit.clone()))),
     );
 
     select! {
         result = net_task => result?,
         result = server_task => result?,
+        Ok(Handover(sender)) = control => {
+            if sender.send(state.into_inner()).is_err() {
+                anyhow::bail!("handover receiver dropped")
+            }
+            return Ok(());
+        }
     }
     anyhow::bail!("unexpected termination of infinite task")
 }
@@ -46,9 +74,13 @@ pub async fn unreplicated() -> anyhow::Result<()> {
 pub async fn pbft(
     config: pbft::PublicParameters,
     index: usize,
+    replica: pbft::replica::State<Null, SocketAddr>,
+    socket: UdpSocket,
     addrs: Vec<SocketAddr>,
+    recv_bound: usize,
+    mut control: UnboundedReceiver<Reconfigure<u8, SocketAddr>>,
 ) -> anyhow::Result<()> {
-    let socket = Arc::new(UdpSocket::bind(addrs[index]).await?);
+    let socket = Arc::new(socket);
 
     let (crypto_sender, mut crypto_receiver) = unbounded_channel();
     let (schedule_sender, mut schedule_receiver) = unbounded_channel();
@@ -58,7 +90,8 @@ pub async fn pbft(
     type PeerNet =
         Encode<pbft::messages::codec::ToReplica<SocketAddr>, IndexNet<SocketAddr, Arc<UdpSocket>>>;
     type DownlinkNet = Encode<pbft::messages::codec::ToClient, Arc<UdpSocket>>;
-    type CryptoWorker = task::work::Sender<Crypto, CryptoContext>;
+    // the crypto work submitted when processing a received message takes over the message's credit
+    type CryptoWorker = Bounded<task::work::Sender<Crypto, CryptoContext>>;
     type CryptoContext = task::erase::Sender<S, Context>;
     type Schedule = task::erase::ScheduleState<S, Context>;
     struct Context {
@@ -86,6 +119,7 @@ pub async fn pbft(
             &mut self.schedule
         }
     }
+    let credit = Credit::new(recv_bound);
     let mut context = Context {
         peer_net: pbft::messages::codec::to_replica_encode(IndexNet::new(
             addrs,
@@ -93,19 +127,27 @@ pub async fn pbft(
             socket.clone(),
         )),
         downlink_net: pbft::messages::codec::to_client_encode(socket.clone()),
-        crypto_worker: crypto_sender,
+        crypto_worker: Bounded(crypto_sender, credit.clone()),
         schedule: Erase::new(ScheduleState::new(schedule_sender)),
     };
     let server_task = run_with_schedule(
-        Untyped::new(pbft::replica::State::new(index as _, Null, config.clone())),
+        Untyped::new(replica),
         &mut context,
         &mut receiver,
         &mut schedule_receiver,
         |context| &mut context.schedule,
     );
-    let net_task = udp::run(
+    let net_task = udp::run_with_credit(
         &socket,
-        pbft::messages::codec::to_replica_decode(Erase::new(sender.clone())),
+        &credit,
+        pbft::messages::codec::to_replica_decode(Erase::new(Bounded(
+            sender.clone(),
+            credit.clone(),
+        ))),
+    );
+    let control_task = forward(
+        &mut control,
+        task::erase::Sender::<S, Context>::new(sender.clone()),
     );
     let crypto_task = run_worker(
         Crypto::new_hardcoded(config.num_replica, index, CryptoFlavor::Schnorrkel)?,
@@ -117,6 +159,7 @@ pub async fn pbft(
         result = server_task => result?,
         result = net_task => result?,
         result = crypto_task => result?,
+        result = control_task => result?,
     }
     anyhow::bail!("unexpected termination of infinite task")
 }